@@ -0,0 +1,39 @@
+#![no_main]
+
+// Runs arbitrary ROMs with arbitrary key input through the interpreter.
+// Emulator errors are fine, panics are not.
+//
+// Input layout: the first byte is the number of key input bytes that
+// follow, the rest is the ROM. Every two key bytes are the keys held
+// during one frame, so FX0A sees keys go down and come back up.
+
+use libfuzzer_sys::fuzz_target;
+
+use chip_8::{emulator::Chip8, keyboard::MaskKeypad, FONTS};
+
+// Frames run per input
+const FRAMES: u32 = 30;
+// Instruction time giving 1000 instructions per frame
+const INSTRUCTION_TIME_NS: u128 = 16_666;
+
+fuzz_target!(|data: &[u8]| {
+    let (&key_len, rest) = match data.split_first() {
+        Some(split) => split,
+        None => return,
+    };
+    let (keys, rom) = rest.split_at((key_len as usize).min(rest.len()));
+
+    let mut chip8 = Chip8::new(rom.to_vec(), FONTS, 0, INSTRUCTION_TIME_NS, false);
+    chip8.seed(0);
+    let mut held = keys.chunks(2);
+    for _ in 0..FRAMES {
+        let mask = match held.next() {
+            Some(&[lo, hi]) => u16::from_le_bytes([lo, hi]),
+            Some(&[lo]) => lo as u16,
+            _ => 0,
+        };
+        if chip8.run_frame(&mut MaskKeypad(mask)).is_err() {
+            break;
+        }
+    }
+});
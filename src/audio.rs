@@ -1,33 +1,26 @@
-use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired, AudioStatus};
+use sdl2::audio::{AudioCallback, AudioDevice, AudioSpecDesired};
 use sdl2::Sdl;
 
-struct SquareWave {
-    phase_inc: f32,
-    phase: f32,
-    volume: f32,
-}
-impl AudioCallback for SquareWave {
+use crate::tone::{Synth, Tone};
+
+impl AudioCallback for Synth {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        // Generate a square wave
-        for x in out.iter_mut() {
-            *x = if self.phase <= 0.5 {
-                self.volume
-            } else {
-                -self.volume
-            };
-            self.phase = (self.phase + self.phase_inc) % 1.0;
-        }
+        self.fill(out);
     }
 }
 
+// The device always runs and plays silence between beeps, so starting and
+// stopping a beep only changes the envelope of the synth
 pub struct Beep {
-    device: AudioDevice<SquareWave>,
+    device: AudioDevice<Synth>,
+    playing: bool,
+    muted: bool,
 }
 
 impl Beep {
-    pub fn new(sdl_context: &Sdl) -> Self {
+    pub fn new(sdl_context: &Sdl, tone: Tone) -> Self {
         let audio_subsystem = sdl_context.audio().unwrap();
 
         let desired_spec = AudioSpecDesired {
@@ -38,27 +31,35 @@ impl Beep {
 
         let device = audio_subsystem
             .open_playback(None, &desired_spec, |spec| {
-                // initialize the audio callback
-                SquareWave {
-                    phase_inc: 440.0 / spec.freq as f32,
-                    phase: 0.0,
-                    volume: 0.2,
-                }
+                Synth::new(tone, spec.freq as u32)
             })
             .unwrap();
+        device.resume();
 
-        Beep { device }
+        Beep {
+            device,
+            playing: false,
+            muted: false,
+        }
     }
 
-    pub fn play(&self) {
-        if self.device.status() == AudioStatus::Stopped
-            || self.device.status() == AudioStatus::Paused
-        {
-            self.device.resume();
+    // Starts or stops the beep. The audio thread is only locked when this
+    // changes, not every time through the main loop
+    pub fn set_playing(&mut self, playing: bool) {
+        if playing != self.playing {
+            self.playing = playing;
+            self.device.lock().set_gate(playing);
         }
     }
 
-    pub fn pause(&self) {
-        self.device.pause();
+    pub fn is_muted(&self) -> bool {
+        self.muted
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted != self.muted {
+            self.muted = muted;
+            self.device.lock().set_muted(muted);
+        }
     }
 }
@@ -0,0 +1,320 @@
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fmt::Write,
+    fs,
+    panic::{self, AssertUnwindSafe},
+    path::Path,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Arc,
+    },
+    thread,
+};
+
+use serde_json::json;
+
+use crate::{
+    emulator::{is_known_opcode, Chip8, Quirks, Status},
+    headless::{framebuffer_hash, ScriptedKeypad},
+    util::crc32,
+    DEF_IPS, FONTS, RAM_SIZE,
+};
+
+// Smoke tests a library of ROMs: each runs headlessly with no input on a
+// pool of worker threads, and the report tells which crashed, halted or
+// ran into instructions the interpreter does not know.
+
+// How a ROM's run ended
+#[derive(Clone, Debug, PartialEq)]
+pub enum Outcome {
+    // Ran all frames
+    Running,
+    // Jumped to itself, the usual way of ending a program
+    Halted { frame: u64, pc: usize },
+    // Still waiting for a key press with FX0A after the last frame
+    WaitingForKey { pc: usize },
+    // Emulator error or panic
+    Crashed { frame: u64, error: String },
+}
+
+impl Outcome {
+    fn name(&self) -> &'static str {
+        match self {
+            Outcome::Running => "running",
+            Outcome::Halted { .. } => "halted",
+            Outcome::WaitingForKey { .. } => "waiting",
+            Outcome::Crashed { .. } => "crashed",
+        }
+    }
+
+    fn detail(&self) -> String {
+        match self {
+            Outcome::Running => String::new(),
+            Outcome::Halted { frame, pc } => format!("at 0x{:03x}, frame {}", pc, frame),
+            Outcome::WaitingForKey { pc } => format!("at 0x{:03x}", pc),
+            Outcome::Crashed { frame, error } => format!("{} (frame {})", error, frame),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct RomReport {
+    pub name: String,
+    pub rom_hash: u32,
+    pub outcome: Outcome,
+    pub frames: u64,               // Frames run before the run ended
+    pub unknown_opcodes: Vec<u16>, // Unknown instructions executed, sorted
+    pub frame_hash: u32,           // Hash of the final framebuffer
+}
+
+// Runs a single ROM for a number of frames, stopping early when it halts
+pub fn run_rom(name: &str, rom: Vec<u8>, frames: u64, quirks: Quirks) -> RomReport {
+    let rom_hash = crc32(&rom);
+    let mut chip8 = Chip8::new(rom, FONTS, 0, 1e9 as u128 / DEF_IPS as u128, false);
+    chip8.quirks = quirks;
+    chip8.seed(0);
+
+    let unknown = Rc::new(RefCell::new(BTreeSet::new()));
+    let seen = Rc::clone(&unknown);
+    chip8.on_before_instruction(move |_, instr| {
+        if !is_known_opcode(instr) {
+            seen.borrow_mut().insert(instr);
+        }
+    });
+
+    let mut keypad = ScriptedKeypad::default();
+    let mut outcome = Outcome::Running;
+    let mut frame = 0;
+    while frame < frames {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| chip8.run_frame(&mut keypad)));
+        let error = match result {
+            Ok(Ok(())) => None,
+            Ok(Err(error)) => Some(error.to_string()),
+            Err(panic) => Some(panic_message(panic)),
+        };
+        if let Some(error) = error {
+            outcome = Outcome::Crashed { frame, error };
+            break;
+        }
+        frame += 1;
+        if instruction_at(&chip8, chip8.pc) == 0x1000 | chip8.pc as u16 {
+            outcome = Outcome::Halted {
+                frame,
+                pc: chip8.pc,
+            };
+            break;
+        }
+    }
+    if outcome == Outcome::Running && matches!(chip8.status, Status::WaitingForKey { .. }) {
+        outcome = Outcome::WaitingForKey { pc: chip8.pc };
+    }
+
+    let unknown_opcodes = unknown.borrow().iter().copied().collect();
+    RomReport {
+        name: name.to_string(),
+        rom_hash,
+        outcome,
+        frames: frame,
+        unknown_opcodes,
+        frame_hash: framebuffer_hash(&chip8.display),
+    }
+}
+
+fn instruction_at(chip8: &Chip8, addr: usize) -> u16 {
+    ((chip8.ram[addr % RAM_SIZE] as u16) << 8) | chip8.ram[(addr + 1) % RAM_SIZE] as u16
+}
+
+fn panic_message(panic: Box<dyn std::any::Any + Send>) -> String {
+    let message = match panic.downcast_ref::<&str>() {
+        Some(s) => s.to_string(),
+        None => panic.downcast_ref::<String>().cloned().unwrap_or_default(),
+    };
+    format!("Panic: {}", message)
+}
+
+// Reads the .ch8 files of a directory, sorted by name
+pub fn load_dir(dir: &str) -> Result<Vec<(String, Vec<u8>)>, String> {
+    let entries =
+        fs::read_dir(dir).map_err(|e| format!("Could not read directory {}: {}", dir, e))?;
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok().map(|e| e.path()))
+        .filter(|p| p.is_file() && has_rom_extension(p))
+        .collect();
+    paths.sort();
+
+    let mut roms = Vec::new();
+    for path in paths {
+        let rom = fs::read(&path).map_err(|e| format!("Could not read {:?}: {}", path, e))?;
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        roms.push((name, rom));
+    }
+    Ok(roms)
+}
+
+fn has_rom_extension(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|e| e.eq_ignore_ascii_case("ch8"))
+}
+
+// Runs ROMs on `threads` worker threads. Reports are in the order of `roms`
+pub fn run_all(
+    roms: Vec<(String, Vec<u8>)>,
+    frames: u64,
+    quirks: Quirks,
+    threads: usize,
+) -> Vec<RomReport> {
+    let roms = Arc::new(roms);
+    let next = Arc::new(AtomicUsize::new(0));
+    let (tx, rx) = mpsc::channel();
+
+    let workers: Vec<_> = (0..threads.max(1).min(roms.len()))
+        .map(|_| {
+            let (roms, next, tx) = (Arc::clone(&roms), Arc::clone(&next), tx.clone());
+            thread::spawn(move || loop {
+                let i = next.fetch_add(1, Ordering::SeqCst);
+                let (name, rom) = match roms.get(i) {
+                    Some(entry) => entry,
+                    None => break,
+                };
+                let report = run_rom(name, rom.clone(), frames, quirks);
+                if tx.send((i, report)).is_err() {
+                    break;
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+
+    let mut reports: Vec<(usize, RomReport)> = rx.iter().collect();
+    for worker in workers {
+        worker.join().unwrap();
+    }
+    reports.sort_by_key(|(i, _)| *i);
+    reports.into_iter().map(|(_, report)| report).collect()
+}
+
+// Summary table with a line per ROM and totals
+pub fn table(reports: &[RomReport]) -> String {
+    let width = reports
+        .iter()
+        .map(|r| r.name.len())
+        .max()
+        .unwrap_or(0)
+        .max(3);
+    let mut out = String::new();
+    writeln!(
+        out,
+        "{:w$}  HASH      RESULT    FRAMES  FRAME     NOTES",
+        "ROM",
+        w = width
+    )
+    .unwrap();
+    for report in reports {
+        let mut notes = report.outcome.detail();
+        if !report.unknown_opcodes.is_empty() {
+            let opcodes: Vec<String> = report
+                .unknown_opcodes
+                .iter()
+                .map(|op| format!("{:04x}", op))
+                .collect();
+            if !notes.is_empty() {
+                notes.push_str("; ");
+            }
+            write!(notes, "unknown opcodes {}", opcodes.join(" ")).unwrap();
+        }
+        let line = format!(
+            "{:w$}  {:08x}  {:8}  {:>6}  {:08x}  {}",
+            report.name,
+            report.rom_hash,
+            report.outcome.name(),
+            report.frames,
+            report.frame_hash,
+            notes,
+            w = width
+        );
+        writeln!(out, "{}", line.trim_end()).unwrap();
+    }
+
+    let count = |name| reports.iter().filter(|r| r.outcome.name() == name).count();
+    writeln!(
+        out,
+        "\n{} ROMs: {} running, {} halted, {} waiting for a key, {} crashed, {} with unknown opcodes",
+        reports.len(),
+        count("running"),
+        count("halted"),
+        count("waiting"),
+        count("crashed"),
+        reports.iter().filter(|r| !r.unknown_opcodes.is_empty()).count()
+    )
+    .unwrap();
+    out
+}
+
+// JSON report, a list with an object per ROM
+pub fn json(reports: &[RomReport]) -> String {
+    let list: Vec<_> = reports
+        .iter()
+        .map(|r| {
+            let mut entry = json!({
+                "rom": r.name,
+                "rom_hash": format!("{:08x}", r.rom_hash),
+                "result": r.outcome.name(),
+                "frames": r.frames,
+                "frame_hash": format!("{:08x}", r.frame_hash),
+                "unknown_opcodes": r
+                    .unknown_opcodes
+                    .iter()
+                    .map(|op| format!("{:04x}", op))
+                    .collect::<Vec<_>>(),
+            });
+            match &r.outcome {
+                Outcome::Halted { pc, .. } | Outcome::WaitingForKey { pc } => {
+                    entry["pc"] = json!(pc);
+                }
+                Outcome::Crashed { error, .. } => entry["error"] = json!(error),
+                Outcome::Running => (),
+            }
+            entry
+        })
+        .collect();
+    serde_json::to_string_pretty(&list).unwrap()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn rom(program: &[u16]) -> Vec<u8> {
+        program.iter().flat_map(|i| i.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn classifies_runs() {
+        let roms = vec![
+            // Loop forever
+            ("loop".to_string(), rom(&[0x7001, 0x1200])),
+            // Unknown instruction, then halt
+            ("halt".to_string(), rom(&[0x8128, 0x1202])),
+            // Wait for a key
+            ("wait".to_string(), rom(&[0xF00A])),
+            // Return with an empty stack
+            ("crash".to_string(), rom(&[0x00EE])),
+        ];
+        let reports = run_all(roms, 10, Quirks::default(), 3);
+
+        let outcomes: Vec<&str> = reports.iter().map(|r| r.outcome.name()).collect();
+        assert_eq!(outcomes, ["running", "halted", "waiting", "crashed"]);
+        assert_eq!(reports[0].frames, 10);
+        assert_eq!(
+            reports[1].outcome,
+            Outcome::Halted {
+                frame: 1,
+                pc: 0x202
+            }
+        );
+        assert_eq!(reports[1].unknown_opcodes, [0x8128]);
+        assert!(reports[0].unknown_opcodes.is_empty());
+    }
+}
@@ -0,0 +1,283 @@
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::Write,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use crate::{
+    emulator::{opcode_pattern, Backend, Chip8, Chip8Error, Quirks},
+    headless::ScriptedKeypad,
+    symbols::Symbols,
+    FONTS, FRAME_TIME_NS,
+};
+
+// Measures raw interpreter throughput: the machine runs frame after frame
+// as fast as possible, with no rendering and no input. A second run with
+// hooks timing each instruction gives a per-opcode breakdown, its times
+// include the cost of the measurement. Timing instructions needs hooks, so
+// the breakdown always comes from the interpreter. The second run repeats
+// the instructions of the first within the same time limit, so with a
+// duration it may stop early and break down only part of them.
+
+// When a benchmark stops
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Limit {
+    Instructions(u64),
+    Duration(Duration),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct OpcodeStats {
+    pub pattern: &'static str, // "8XY4", or "????" for unknown instructions
+    pub count: u64,
+    pub time: Duration, // Total time spent in the instruction
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct AddressStats {
+    pub addr: usize,
+    pub count: u64,
+    pub time: Duration,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BenchReport {
+    pub instructions: u64,
+    pub frames: u64,
+    pub elapsed: Duration,
+    pub error: Option<Chip8Error>,    // Error that ended the run early
+    pub opcodes: Vec<OpcodeStats>,    // Sorted by time, slowest first
+    pub addresses: Vec<AddressStats>, // Instructions run at each address, by address
+}
+
+impl BenchReport {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64().max(1e-9)
+    }
+
+    pub fn frame_time(&self) -> Duration {
+        Duration::from_secs_f64(self.elapsed.as_secs_f64() / self.frames.max(1) as f64)
+    }
+}
+
+pub fn run(
+    rom: &[u8],
+    instruction_time_ns: u128,
+    quirks: Quirks,
+    backend: Backend,
+    limit: Limit,
+) -> BenchReport {
+    let mut chip8 = machine(rom, instruction_time_ns, quirks);
+    chip8.backend = backend;
+    let (max_instructions, max_time) = match limit {
+        Limit::Instructions(n) => (n, None),
+        Limit::Duration(d) => (u64::MAX, Some(d)),
+    };
+    let (frames, elapsed, error) = run_frames(&mut chip8, max_instructions, max_time);
+    let instructions = chip8.instructions();
+
+    // Instrumented run over the same instructions
+    let mut chip8 = machine(rom, instruction_time_ns, quirks);
+    let stats = Rc::new(RefCell::new(HashMap::new()));
+    let address_stats = Rc::new(RefCell::new(BTreeMap::new()));
+    let start = Rc::new(RefCell::new((Instant::now(), 0)));
+    let before = Rc::clone(&start);
+    chip8.on_before_instruction(move |chip8, _| *before.borrow_mut() = (Instant::now(), chip8.pc));
+    let after = Rc::clone(&stats);
+    let after_address = Rc::clone(&address_stats);
+    chip8.on_after_instruction(move |_, instr| {
+        let (start, pc) = *start.borrow();
+        let time = start.elapsed();
+        let mut stats = after.borrow_mut();
+        let entry = stats
+            .entry(opcode_pattern(instr).unwrap_or("????"))
+            .or_insert((0, Duration::default()));
+        entry.0 += 1;
+        entry.1 += time;
+        let mut address_stats = after_address.borrow_mut();
+        let entry = address_stats.entry(pc).or_insert((0, Duration::default()));
+        entry.0 += 1;
+        entry.1 += time;
+    });
+    run_frames(&mut chip8, instructions, max_time);
+
+    let mut opcodes: Vec<OpcodeStats> = stats
+        .borrow()
+        .iter()
+        .map(|(&pattern, &(count, time))| OpcodeStats {
+            pattern,
+            count,
+            time,
+        })
+        .collect();
+    opcodes.sort_by(|a, b| b.time.cmp(&a.time).then(a.pattern.cmp(b.pattern)));
+    let addresses = address_stats
+        .borrow()
+        .iter()
+        .map(|(&addr, &(count, time))| AddressStats { addr, count, time })
+        .collect();
+
+    BenchReport {
+        instructions,
+        frames,
+        elapsed,
+        error,
+        opcodes,
+        addresses,
+    }
+}
+
+fn machine(rom: &[u8], instruction_time_ns: u128, quirks: Quirks) -> Chip8 {
+    let mut chip8 = Chip8::new(rom.to_vec(), FONTS, 0, instruction_time_ns, false);
+    chip8.quirks = quirks;
+    chip8.seed(0);
+    chip8
+}
+
+// Runs whole frames until either limit is reached or the machine errors
+fn run_frames(
+    chip8: &mut Chip8,
+    max_instructions: u64,
+    max_time: Option<Duration>,
+) -> (u64, Duration, Option<Chip8Error>) {
+    let mut keypad = ScriptedKeypad::default();
+    let mut frames = 0;
+    let start = Instant::now();
+    loop {
+        if chip8.instructions() >= max_instructions
+            || max_time.is_some_and(|d| start.elapsed() >= d)
+        {
+            break;
+        }
+        if let Err(error) = chip8.run_frame(&mut keypad) {
+            return (frames, start.elapsed(), Some(error));
+        }
+        frames += 1;
+    }
+    (frames, start.elapsed(), None)
+}
+
+// Human readable report. With labels, time is also broken down by the
+// label each instruction falls under
+pub fn summary(report: &BenchReport, instruction_time_ns: u128, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    let ips = report.instructions_per_second();
+    let real_ips = 1e9 / instruction_time_ns as f64;
+    writeln!(
+        out,
+        "Instructions: {} in {:.3} s",
+        report.instructions,
+        report.elapsed.as_secs_f64()
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "Speed: {:.2} M instructions/s, {:.0}x real time at {:.0} instructions/s",
+        ips / 1e6,
+        ips / real_ips,
+        real_ips
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "Frames: {}, {:.3} us per frame ({:.0}x the {:.3} ms budget)",
+        report.frames,
+        report.frame_time().as_secs_f64() * 1e6,
+        FRAME_TIME_NS as f64 / report.frame_time().as_nanos().max(1) as f64,
+        FRAME_TIME_NS as f64 / 1e6
+    )
+    .unwrap();
+    if let Some(error) = &report.error {
+        writeln!(out, "Stopped early: {}", error).unwrap();
+    }
+
+    let total = total_time(report).max(1e-9);
+    // Shares are of the instructions the breakdown covers
+    let counted: u64 = report.opcodes.iter().map(|s| s.count).sum();
+    if counted < report.instructions {
+        writeln!(
+            out,
+            "Breakdown of the first {} instructions, the time ran out",
+            counted
+        )
+        .unwrap();
+    }
+    writeln!(out, "\nOPCODE       COUNT   SHARE   NS/INSTR   TIME").unwrap();
+    for stats in &report.opcodes {
+        writeln!(
+            out,
+            "{:6}  {:>10}  {:>5.1}%  {:>9.1}  {:>5.1}%",
+            stats.pattern,
+            stats.count,
+            100.0 * stats.count as f64 / counted.max(1) as f64,
+            stats.time.as_nanos() as f64 / stats.count.max(1) as f64,
+            100.0 * stats.time.as_secs_f64() / total
+        )
+        .unwrap();
+    }
+
+    if !symbols.labels.is_empty() {
+        let mut labels: BTreeMap<&str, (u64, Duration)> = BTreeMap::new();
+        for stats in &report.addresses {
+            let label = match symbols.labels.range(..=stats.addr).next_back() {
+                Some((_, label)) => label.as_str(),
+                None => "(no label)",
+            };
+            let entry = labels.entry(label).or_default();
+            entry.0 += stats.count;
+            entry.1 += stats.time;
+        }
+        let mut labels: Vec<(&str, (u64, Duration))> = labels.into_iter().collect();
+        labels.sort_by_key(|(_, (_, time))| std::cmp::Reverse(*time));
+        writeln!(out, "\nLABEL                        COUNT   SHARE   TIME").unwrap();
+        for (label, (count, time)) in labels {
+            writeln!(
+                out,
+                "{:24}  {:>10}  {:>5.1}%  {:>5.1}%",
+                label,
+                count,
+                100.0 * count as f64 / counted.max(1) as f64,
+                100.0 * time.as_secs_f64() / total
+            )
+            .unwrap();
+        }
+    }
+    out
+}
+
+fn total_time(report: &BenchReport) -> f64 {
+    report.opcodes.iter().map(|s| s.time.as_secs_f64()).sum()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn counts_opcodes() {
+        // V0 += 1, V1 = V0, jump back
+        let rom = [0x70, 0x01, 0x81, 0x00, 0x12, 0x00];
+        let report = run(
+            &rom,
+            1_000_000,
+            Quirks::default(),
+            Backend::Threaded,
+            Limit::Instructions(300),
+        );
+
+        assert_eq!(report.error, None);
+        assert!(report.instructions >= 300);
+        let counts: Vec<(&str, u64)> = report
+            .opcodes
+            .iter()
+            .map(|s| (s.pattern, s.count))
+            .collect();
+        let total: u64 = counts.iter().map(|(_, n)| n).sum();
+        assert_eq!(total, report.instructions);
+        for pattern in &["7XNN", "8XY0", "1NNN"] {
+            assert!(counts.iter().any(|(p, _)| p == pattern));
+        }
+    }
+}
@@ -0,0 +1,340 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{
+    emulator::{decode, Decoded, Op},
+    symbols::Symbols,
+    MAX_ROM_SIZE, PROGRAM_LOC,
+};
+
+// Static control flow graph of a ROM. Code is discovered from PROGRAM_LOC
+// by following jumps, calls, skips and returns, so data the program never
+// branches to stays out of the graph. Computed BNNN jumps cannot be
+// followed and end in unresolved edges.
+
+// How control leaves a basic block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exit {
+    // Falls through into the block at the address
+    Next(usize),
+    // 1NNN
+    Jump(usize),
+    // Skip instruction, on to `next` or over it to `skip`
+    Skip { next: usize, skip: usize },
+    // 2NNN, back to `ret` after the subroutine returns
+    Call { target: usize, ret: usize },
+    // 00EE
+    Return,
+    // BNNN, to V0 + NNN
+    Computed(u16),
+    // Runs off the end of the ROM
+    End,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    pub start: usize,
+    pub instructions: Vec<(usize, Decoded)>,
+    pub exit: Exit,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfg {
+    pub rom_end: usize,                           // First address after the ROM
+    pub blocks: BTreeMap<usize, Block>,           // By start address
+    pub subroutines: BTreeMap<usize, Vec<usize>>, // Block starts by entry point
+}
+
+impl Cfg {
+    // Whether a whole instruction at `addr` is in the ROM
+    pub fn in_rom(&self, addr: usize) -> bool {
+        addr >= PROGRAM_LOC && addr + 2 <= self.rom_end
+    }
+}
+
+impl Block {
+    // Address of the instruction ending the block
+    pub fn last(&self) -> usize {
+        self.start + 2 * (self.instructions.len() - 1)
+    }
+}
+
+impl Exit {
+    // Addresses control can go to next, calls included
+    pub fn targets(&self) -> Vec<usize> {
+        match *self {
+            Exit::Next(addr) | Exit::Jump(addr) => vec![addr],
+            Exit::Skip { next, skip } => vec![next, skip],
+            Exit::Call { target, ret } => vec![target, ret],
+            Exit::Return | Exit::Computed(_) | Exit::End => vec![],
+        }
+    }
+}
+
+// How an instruction at `addr` leaves its block, if it does
+fn exit(addr: usize, decoded: &Decoded) -> Option<Exit> {
+    let nnn = decoded.nnn as usize;
+    match decoded.op {
+        Op::Jp => Some(Exit::Jump(nnn)),
+        Op::Call => Some(Exit::Call {
+            target: nnn,
+            ret: addr + 2,
+        }),
+        Op::Ret => Some(Exit::Return),
+        Op::JpV0 => Some(Exit::Computed(decoded.nnn)),
+        Op::SeImm | Op::SneImm | Op::SeReg | Op::SneReg | Op::Skp | Op::Sknp => Some(Exit::Skip {
+            next: addr + 2,
+            skip: addr + 4,
+        }),
+        _ => None,
+    }
+}
+
+pub fn analyze(rom: &[u8]) -> Cfg {
+    let rom_end = PROGRAM_LOC + rom.len().min(MAX_ROM_SIZE);
+    let in_rom = |addr: usize| addr >= PROGRAM_LOC && addr + 2 <= rom_end;
+    let fetch = |addr: usize| {
+        let at = addr - PROGRAM_LOC;
+        decode(((rom[at] as u16) << 8) | rom[at + 1] as u16)
+    };
+
+    // Every reachable instruction, and the ones starting blocks
+    let mut reached = BTreeSet::new();
+    let mut leaders = BTreeSet::new();
+    let mut entries = BTreeSet::new();
+    let mut pending = vec![PROGRAM_LOC];
+    leaders.insert(PROGRAM_LOC);
+    entries.insert(PROGRAM_LOC);
+    while let Some(addr) = pending.pop() {
+        if !in_rom(addr) || !reached.insert(addr) {
+            continue;
+        }
+        match exit(addr, &fetch(addr)) {
+            Some(exit) => {
+                if let Exit::Call { target, .. } = exit {
+                    entries.insert(target);
+                }
+                for target in exit.targets() {
+                    leaders.insert(target);
+                    pending.push(target);
+                }
+            }
+            None => pending.push(addr + 2),
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for &start in leaders.iter().filter(|&&addr| reached.contains(&addr)) {
+        let mut instructions = Vec::new();
+        let mut addr = start;
+        let exit = loop {
+            let decoded = fetch(addr);
+            instructions.push((addr, decoded));
+            if let Some(exit) = exit(addr, &decoded) {
+                break exit;
+            }
+            addr += 2;
+            if !in_rom(addr) {
+                break Exit::End;
+            }
+            if leaders.contains(&addr) {
+                break Exit::Next(addr);
+            }
+        };
+        blocks.insert(
+            start,
+            Block {
+                start,
+                instructions,
+                exit,
+            },
+        );
+    }
+
+    // A block belongs to the first subroutine reaching it without calls,
+    // and every call target starts its own subroutine
+    let entries: Vec<usize> = entries
+        .into_iter()
+        .filter(|addr| blocks.contains_key(addr))
+        .collect();
+    let mut owner: BTreeMap<usize, usize> = entries.iter().map(|&e| (e, e)).collect();
+    for &entry in &entries {
+        let mut pending = vec![entry];
+        while let Some(start) = pending.pop() {
+            let targets = match blocks[&start].exit {
+                Exit::Call { ret, .. } => vec![ret],
+                exit => exit.targets(),
+            };
+            for target in targets {
+                if blocks.contains_key(&target) && !owner.contains_key(&target) {
+                    owner.insert(target, entry);
+                    pending.push(target);
+                }
+            }
+        }
+    }
+    let mut subroutines: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for (start, entry) in owner {
+        subroutines.entry(entry).or_default().push(start);
+    }
+
+    Cfg {
+        rom_end,
+        blocks,
+        subroutines,
+    }
+}
+
+// Graphviz DOT graph, one cluster per subroutine. With symbols, operands
+// and subroutines show their labels and instructions their source lines
+pub fn dot(cfg: &Cfg, symbols: &Symbols) -> String {
+    let mut out = String::new();
+    writeln!(out, "digraph cfg {{").unwrap();
+    writeln!(out, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+    for (entry, starts) in &cfg.subroutines {
+        let name = match (symbols.labels.get(entry), *entry) {
+            (Some(label), _) => label.clone(),
+            (None, PROGRAM_LOC) => "main".to_string(),
+            (None, entry) => format!("sub_{:03x}", entry),
+        };
+        writeln!(out, "    subgraph cluster_{:03x} {{", entry).unwrap();
+        writeln!(out, "        label=\"{}\";", escape(&name)).unwrap();
+        for start in starts {
+            let mut label = String::new();
+            let mut source = None;
+            for (addr, decoded) in &cfg.blocks[start].instructions {
+                let mut line = format!("{:03x}  {}", addr, symbols.disassemble(decoded));
+                // Source lines where they change
+                if symbols.source(*addr) != source {
+                    source = symbols.source(*addr);
+                    if let Some(source) = source {
+                        write!(line, "  ; {}", source).unwrap();
+                    }
+                }
+                // Lines are escaped on their own, `\l` ends a left-aligned line
+                write!(label, "{}\\l", escape(&line)).unwrap();
+            }
+            writeln!(out, "        b{:03x} [label=\"{}\"];", start, label).unwrap();
+        }
+        writeln!(out, "    }}").unwrap();
+    }
+
+    // Targets outside the ROM get a node of their own
+    let mut outside = BTreeSet::new();
+    let mut edge = |out: &mut String, from: usize, to: usize, attrs: &str| {
+        if !cfg.blocks.contains_key(&to) {
+            outside.insert(to);
+        }
+        writeln!(out, "    b{:03x} -> b{:03x}{};", from, to, attrs).unwrap();
+    };
+    for (start, block) in &cfg.blocks {
+        match block.exit {
+            Exit::Next(addr) => edge(&mut out, *start, addr, ""),
+            Exit::Jump(addr) => edge(&mut out, *start, addr, ""),
+            Exit::Skip { next, skip } => {
+                edge(&mut out, *start, next, "");
+                edge(&mut out, *start, skip, " [label=\"skip\"]");
+            }
+            Exit::Call { target, ret } => {
+                edge(&mut out, *start, target, " [style=dashed, label=\"call\"]");
+                edge(&mut out, *start, ret, " [label=\"return\"]");
+            }
+            Exit::Computed(nnn) => {
+                writeln!(
+                    out,
+                    "    unresolved_{:03x} [shape=plaintext, label=\"?\"];",
+                    start
+                )
+                .unwrap();
+                writeln!(
+                    out,
+                    "    b{:03x} -> unresolved_{:03x} [style=dotted, label=\"V0 + 0x{:03X}\"];",
+                    start, start, nnn
+                )
+                .unwrap();
+            }
+            Exit::Return | Exit::End => (),
+        }
+    }
+    for addr in outside {
+        writeln!(
+            out,
+            "    b{:03x} [shape=plaintext, label=\"{:03x} (outside the ROM)\"];",
+            addr, addr
+        )
+        .unwrap();
+    }
+    writeln!(out, "}}").unwrap();
+    out
+}
+
+// Backslashes and quotes in DOT strings, from symbol files. Nodes are
+// boxes, not records, so braces and bars need no escaping
+fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn finds_blocks_and_subroutines() {
+        let program: [u16; 8] = [
+            0x6000, // 200: V0 = 0
+            0x220C, // 202: call 0x20c
+            0x3005, // 204: skip if V0 == 5
+            0x1200, // 206: loop
+            0x1208, // 208: halt
+            0xB300, // 20a: unreachable computed jump
+            0x7001, // 20c: V0 += 1
+            0x00EE, // 20e: return
+        ];
+        let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let cfg = analyze(&rom);
+
+        let exits: Vec<(usize, Exit)> = cfg.blocks.values().map(|b| (b.start, b.exit)).collect();
+        assert_eq!(
+            exits,
+            vec![
+                (
+                    0x200,
+                    Exit::Call {
+                        target: 0x20c,
+                        ret: 0x204
+                    }
+                ),
+                (
+                    0x204,
+                    Exit::Skip {
+                        next: 0x206,
+                        skip: 0x208
+                    }
+                ),
+                (0x206, Exit::Jump(0x200)),
+                (0x208, Exit::Jump(0x208)),
+                (0x20c, Exit::Return),
+            ]
+        );
+        assert_eq!(cfg.blocks[&0x200].instructions.len(), 2);
+        assert_eq!(cfg.subroutines[&0x200], vec![0x200, 0x204, 0x206, 0x208]);
+        assert_eq!(cfg.subroutines[&0x20c], vec![0x20c]);
+
+        let dot = dot(&cfg, &Symbols::default());
+        assert!(dot.contains("b200 -> b20c [style=dashed, label=\"call\"];"));
+        assert!(dot.contains("202  CALL 0x20C\\l"));
+        assert!(!dot.contains("b20a"));
+    }
+
+    #[test]
+    fn escapes_labels() {
+        let rom = [0x22, 0x04, 0x12, 0x02, 0x00, 0xEE];
+        let symbols = Symbols::parse("a\\\"b = 204\n200 : dir\\game.8o:1\n").unwrap();
+        let dot = dot(&analyze(&rom), &symbols);
+        assert!(dot.contains("label=\"a\\\\\\\"b\";"));
+        assert!(dot.contains("200  CALL a\\\\\\\"b  ; dir\\\\game.8o:1\\l"));
+    }
+}
@@ -0,0 +1,441 @@
+use std::{
+    fmt::{self, Write as _},
+    fs,
+    io::{self, BufRead, Write},
+};
+
+use crate::{emulator::Chip8, NUM_REGISTERS, RAM_SIZE};
+
+// Cheats: values frozen in RAM or registers every frame, and a RAM search
+// to find the addresses worth freezing.
+//
+// Cheat files hold one section per ROM, keyed by the CRC-32 of the ROM as
+// printed at startup. Addresses and values are hex:
+//
+//   # Airplane
+//   [8f3a9b12]
+//   3a1 = 05   # lives
+//   v3 = 09
+
+// Maximum number of search candidates listed
+const MAX_LISTED: usize = 20;
+
+// Location a cheat writes to
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Target {
+    Ram(u16),
+    Register(u8),
+}
+
+impl Target {
+    // Parses a hex RAM address ("3a1") or a register ("v3")
+    pub fn parse(s: &str) -> Result<Self, String> {
+        let s = s.trim();
+        let lower = s.to_ascii_lowercase();
+        if let Some(reg) = lower.strip_prefix('v') {
+            return match u8::from_str_radix(reg, 16) {
+                Ok(r) if (r as usize) < NUM_REGISTERS => Ok(Target::Register(r)),
+                _ => Err(format!("Invalid register '{}'", s)),
+            };
+        }
+        match u16::from_str_radix(lower.trim_start_matches("0x"), 16) {
+            Ok(addr) if (addr as usize) < RAM_SIZE => Ok(Target::Ram(addr)),
+            _ => Err(format!("Invalid address '{}'", s)),
+        }
+    }
+
+    pub fn read(self, chip8: &Chip8) -> u8 {
+        match self {
+            Target::Ram(addr) => chip8.ram[addr as usize],
+            Target::Register(reg) => chip8.registers[reg as usize],
+        }
+    }
+
+    pub fn write(self, chip8: &mut Chip8, value: u8) {
+        match self {
+            Target::Ram(addr) => chip8.write_ram(addr as usize, &[value]),
+            Target::Register(reg) => chip8.registers[reg as usize] = value,
+        }
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self {
+            Target::Ram(addr) => write!(f, "{:03x}", addr),
+            Target::Register(reg) => write!(f, "v{:x}", reg),
+        }
+    }
+}
+
+// A value written to its target every frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Freeze {
+    pub target: Target,
+    pub value: u8,
+}
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Cheats {
+    pub freezes: Vec<Freeze>,
+}
+
+impl Cheats {
+    // Loads the cheats of the ROM with the given hash from a cheat file
+    pub fn load(path: &str, rom_hash: u32) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read cheat file {}: {}", path, e))?;
+        Cheats::parse(&text, rom_hash)
+    }
+
+    // Parses the section of a cheat file for the ROM with the given hash.
+    // Sections may repeat, their entries are merged
+    pub fn parse(text: &str, rom_hash: u32) -> Result<Self, String> {
+        let mut cheats = Cheats::default();
+        let mut in_section = false;
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some(hash) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let hash = u32::from_str_radix(hash.trim(), 16)
+                    .map_err(|_| format!("Line {}: invalid ROM hash '{}'", n + 1, hash))?;
+                in_section = hash == rom_hash;
+                continue;
+            }
+            if !in_section {
+                continue;
+            }
+            let freeze = parse_assignment(line).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+            cheats.freeze(freeze.target, freeze.value);
+        }
+        Ok(cheats)
+    }
+
+    // Freezes a target, replacing any value it was frozen to
+    pub fn freeze(&mut self, target: Target, value: u8) {
+        self.unfreeze(target);
+        self.freezes.push(Freeze { target, value });
+    }
+
+    // Returns false if the target was not frozen
+    pub fn unfreeze(&mut self, target: Target) -> bool {
+        let len = self.freezes.len();
+        self.freezes.retain(|f| f.target != target);
+        self.freezes.len() != len
+    }
+
+    // Writes the frozen values, called once per frame. Values already in
+    // place are not written again, so compiled code covering them is kept
+    pub fn apply(&self, chip8: &mut Chip8) {
+        for freeze in &self.freezes {
+            if freeze.target.read(chip8) != freeze.value {
+                freeze.target.write(chip8, freeze.value);
+            }
+        }
+    }
+
+    // Formats the cheats as a cheat file section
+    pub fn to_section(&self, rom_hash: u32) -> String {
+        let mut out = format!("[{:08x}]\n", rom_hash);
+        for freeze in &self.freezes {
+            writeln!(out, "{} = {:02x}", freeze.target, freeze.value).unwrap();
+        }
+        out
+    }
+
+    // Cheat file text with the sections of the ROM replaced by these cheats
+    pub fn replace_section(&self, text: &str, rom_hash: u32) -> String {
+        let mut out = String::new();
+        let mut in_section = false;
+        for line in text.lines() {
+            let content = line.split('#').next().unwrap().trim();
+            if let Some(hash) = content.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                in_section = u32::from_str_radix(hash.trim(), 16) == Ok(rom_hash);
+            }
+            if !in_section {
+                writeln!(out, "{}", line).unwrap();
+            }
+        }
+        if !out.trim().is_empty() {
+            out = format!("{}\n\n", out.trim_end());
+        } else {
+            out.clear();
+        }
+        out.push_str(&self.to_section(rom_hash));
+        out
+    }
+}
+
+// Parses "TARGET = VALUE"
+fn parse_assignment(s: &str) -> Result<Freeze, String> {
+    let mut parts = s.splitn(2, '=');
+    let target = Target::parse(parts.next().unwrap())?;
+    let value = parts
+        .next()
+        .ok_or_else(|| format!("Expected TARGET = VALUE, got '{}'", s))?;
+    Ok(Freeze {
+        target,
+        value: parse_value(value)?,
+    })
+}
+
+fn parse_value(s: &str) -> Result<u8, String> {
+    let s = s.trim();
+    u8::from_str_radix(s.trim_start_matches("0x"), 16)
+        .map_err(|_| format!("Invalid value '{}', expected a hex byte", s))
+}
+
+// How a search narrows down the candidate addresses
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Comparison {
+    // Current value equals the given value
+    Equal(u8),
+    // Changed since the last snapshot
+    Changed,
+    // Unchanged since the last snapshot
+    Unchanged,
+    // Greater than at the last snapshot
+    Increased,
+    // Less than at the last snapshot
+    Decreased,
+}
+
+impl Comparison {
+    fn matches(self, old: u8, new: u8) -> bool {
+        match self {
+            Comparison::Equal(value) => new == value,
+            Comparison::Changed => new != old,
+            Comparison::Unchanged => new == old,
+            Comparison::Increased => new > old,
+            Comparison::Decreased => new < old,
+        }
+    }
+}
+
+// Narrows down RAM addresses by comparing snapshots
+#[derive(Clone, Debug)]
+pub struct RamSearch {
+    snapshot: Vec<u8>,
+    candidates: Vec<usize>,
+}
+
+impl RamSearch {
+    // Starts a search with every address as a candidate
+    pub fn new(ram: &[u8]) -> Self {
+        RamSearch {
+            snapshot: ram.to_vec(),
+            candidates: (0..ram.len()).collect(),
+        }
+    }
+
+    // Keeps the candidates matching the comparison against the last
+    // snapshot, then takes a new snapshot
+    pub fn filter(&mut self, ram: &[u8], comparison: Comparison) {
+        let snapshot = &self.snapshot;
+        self.candidates
+            .retain(|&addr| comparison.matches(snapshot[addr], ram[addr]));
+        self.snapshot.copy_from_slice(ram);
+    }
+
+    pub fn candidates(&self) -> &[usize] {
+        &self.candidates
+    }
+}
+
+const HELP: &str = "Commands (addresses and values in hex):
+  search eq VALUE    keep addresses holding VALUE
+  search changed     keep addresses changed since the last search
+  search unchanged   keep addresses unchanged since the last search
+  search inc | dec   keep addresses increased/decreased since the last search
+  search reset       start a new search
+  list               show the search candidates
+  poke TARGET VALUE  write a value once, TARGET is an address or v0-vf
+  freeze TARGET VALUE
+  unfreeze TARGET
+  cheats             show the frozen values
+  save               write the frozen values to the cheat file
+  continue           resume the game";
+
+// Interactive cheat console state, kept between console sessions
+pub struct CheatConsole {
+    pub rom_hash: u32,
+    pub cheat_file: Option<String>,
+    search: Option<RamSearch>,
+}
+
+impl CheatConsole {
+    pub fn new(rom_hash: u32, cheat_file: Option<String>) -> Self {
+        CheatConsole {
+            rom_hash,
+            cheat_file,
+            search: None,
+        }
+    }
+
+    // Reads and runs commands from stdin until `continue` or end of input
+    pub fn run(&mut self, chip8: &mut Chip8) {
+        println!("Cheat console, 'help' lists the commands");
+        let stdin = io::stdin();
+        let mut lines = stdin.lock().lines();
+        loop {
+            print!("cheat> ");
+            io::stdout().flush().ok();
+            let line = match lines.next() {
+                Some(Ok(line)) => line,
+                _ => break,
+            };
+            match self.execute(chip8, &line) {
+                Ok(Some(out)) => println!("{}", out),
+                Ok(None) => break,
+                Err(e) => println!("{}", e),
+            }
+        }
+    }
+
+    // Runs a console command, returning its output or None to resume
+    pub fn execute(&mut self, chip8: &mut Chip8, line: &str) -> Result<Option<String>, String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        let out = match args.as_slice() {
+            [] => String::new(),
+            ["c"] | ["continue"] => return Ok(None),
+            ["help"] => HELP.to_string(),
+            ["search", "reset"] => {
+                self.search = Some(RamSearch::new(&chip8.ram));
+                format!("New search of {} addresses", RAM_SIZE)
+            }
+            ["search", comparison @ ..] => {
+                let comparison = match comparison {
+                    ["eq", value] => Comparison::Equal(parse_value(value)?),
+                    ["changed"] => Comparison::Changed,
+                    ["unchanged"] => Comparison::Unchanged,
+                    ["inc"] => Comparison::Increased,
+                    ["dec"] => Comparison::Decreased,
+                    _ => return Err("Unknown search, 'help' lists the commands".to_string()),
+                };
+                match self.search.as_mut() {
+                    Some(search) => search.filter(&chip8.ram, comparison),
+                    None => {
+                        let mut search = RamSearch::new(&chip8.ram);
+                        if let Comparison::Equal(_) = comparison {
+                            search.filter(&chip8.ram, comparison);
+                        } else {
+                            self.search = Some(search);
+                            return Ok(Some(
+                                "Snapshot taken, play on and search again".to_string(),
+                            ));
+                        }
+                        self.search = Some(search);
+                    }
+                }
+                self.list(chip8)
+            }
+            ["list"] => self.list(chip8),
+            ["poke", target, value] => {
+                let target = Target::parse(target)?;
+                target.write(chip8, parse_value(value)?);
+                format!("{} = {:02x}", target, target.read(chip8))
+            }
+            ["freeze", target, value] => {
+                let target = Target::parse(target)?;
+                let value = parse_value(value)?;
+                chip8.cheats.freeze(target, value);
+                target.write(chip8, value);
+                format!("Froze {} = {:02x}", target, value)
+            }
+            ["unfreeze", target] => {
+                let target = Target::parse(target)?;
+                if chip8.cheats.unfreeze(target) {
+                    format!("Unfroze {}", target)
+                } else {
+                    format!("{} is not frozen", target)
+                }
+            }
+            ["cheats"] => {
+                if chip8.cheats.freezes.is_empty() {
+                    "No frozen values".to_string()
+                } else {
+                    chip8.cheats.to_section(self.rom_hash)
+                }
+            }
+            ["save"] => {
+                let path = self
+                    .cheat_file
+                    .as_ref()
+                    .ok_or("No cheat file, start with --cheats FILE to save cheats")?;
+                let text = match fs::read_to_string(path) {
+                    Ok(text) => text,
+                    Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+                    Err(e) => return Err(format!("Could not read cheat file {}: {}", path, e)),
+                };
+                fs::write(path, chip8.cheats.replace_section(&text, self.rom_hash))
+                    .map_err(|e| format!("Could not save cheats to {}: {}", path, e))?;
+                format!("Saved cheats to {}", path)
+            }
+            _ => return Err("Unknown command, 'help' lists the commands".to_string()),
+        };
+        Ok(Some(out))
+    }
+
+    // Lists the search candidates with their current values
+    fn list(&self, chip8: &Chip8) -> String {
+        let candidates = match &self.search {
+            Some(search) => search.candidates(),
+            None => return "No search running".to_string(),
+        };
+        let mut out = format!("{} candidates", candidates.len());
+        for &addr in candidates.iter().take(MAX_LISTED) {
+            write!(out, "\n  {:03x} = {:02x}", addr, chip8.ram[addr]).unwrap();
+        }
+        if candidates.len() > MAX_LISTED {
+            out.push_str("\n  ...");
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn parses_rom_section() {
+        let text = "[00000001]\n3a1 = 05\n\n[00000002] # other\nv3 = 09 # lives\n200=ff\n";
+        let cheats = Cheats::parse(text, 2).unwrap();
+        assert_eq!(
+            cheats.freezes,
+            vec![
+                Freeze {
+                    target: Target::Register(3),
+                    value: 0x09
+                },
+                Freeze {
+                    target: Target::Ram(0x200),
+                    value: 0xFF
+                },
+            ]
+        );
+        assert_eq!(Cheats::parse(&cheats.to_section(2), 2).unwrap(), cheats);
+
+        // Saving replaces the ROM's sections and keeps the others
+        let saved = cheats.replace_section(&format!("{}[00000002]\n5 = 01\n", text), 2);
+        assert_eq!(
+            saved,
+            "[00000001]\n3a1 = 05\n\n[00000002]\nv3 = 09\n200 = ff\n"
+        );
+        assert_eq!(cheats.replace_section(&saved, 2), saved);
+        assert_eq!(cheats.replace_section("", 2), cheats.to_section(2));
+    }
+
+    #[test]
+    fn search_narrows_candidates() {
+        let mut ram = vec![3, 3, 7, 1];
+        let mut search = RamSearch::new(&ram);
+        search.filter(&ram, Comparison::Equal(3));
+        assert_eq!(search.candidates(), [0, 1]);
+        ram[1] = 2;
+        search.filter(&ram, Comparison::Decreased);
+        assert_eq!(search.candidates(), [1]);
+    }
+}
@@ -50,14 +50,7 @@ impl CPU {
     }
 
     fn print(&self, delim: Option<&str>) -> CPUResult<()> {
-        print!(
-            "{}{}",
-            self.get_register(0)?,
-            match delim {
-                Some(val) => val,
-                None => "",
-            }
-        );
+        print!("{}{}", self.get_register(0)?, delim.unwrap_or_default());
         Ok(())
     }
 
@@ -99,11 +92,17 @@ impl CPU {
         let c = ((self.opcode & 0xF000) >> 12) as u8;
         let x = ((self.opcode & 0x0F00) >> 8) as u8;
         let y = ((self.opcode & 0x00F0) >> 4) as u8;
-        let d = ((self.opcode & 0x000F) >> 0) as u8;
+        let d = (self.opcode & 0x000F) as u8;
         (c, x, y, d)
     }
 }
 
+impl Default for CPU {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Display for CPU {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let [r1, r2] = self.registers;
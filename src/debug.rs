@@ -1,21 +1,14 @@
 use std::io::{stdin, stdout, Read, Write};
 
-use crate::NUM_REGISTERS;
+use crate::{emulator::decode, symbols::Symbols, NUM_REGISTERS};
 
-pub fn debug(
-    pc: usize,
-    instr: u16,
-    code: u16,
-    x: usize,
-    y: usize,
-    n: u16,
-    nn: u16,
-    nnn: u16,
-    registers: [u8; NUM_REGISTERS],
-    idx: u16,
-) {
-    println!("");
-    println!("L{:03x}:  {}", pc - 2, debug_instr(code, x, y, n, nn, nnn));
+pub fn debug(pc: usize, instr: u16, registers: [u8; NUM_REGISTERS], idx: u16, symbols: &Symbols) {
+    println!();
+    println!("L{:03x}:  {}", pc - 2, symbols.disassemble(&decode(instr)));
+    let location = symbols.describe(pc - 2);
+    if !location.is_empty() {
+        println!("at:          {}", location);
+    }
     println!("instr:       0x{:04x}", instr);
     println!(
         "V0: 0x{:04x}  V1: 0x{:04x}  V2: 0x{:04x}",
@@ -53,7 +46,7 @@ pub fn debug(
 pub fn debug_instr(code: u16, x: usize, y: usize, n: u16, nn: u16, nnn: u16) -> String {
     match code {
         // 00E0 - clear screen
-        0x0000 => format!("CLS"),
+        0x0000 => "CLS".to_string(),
         // 1NNN - jump
         0x1000 => format!("JMP 0x{:04x}", nnn),
         // 6XNN - set register VX to NN
@@ -73,21 +66,22 @@ pub fn debug_instr(code: u16, x: usize, y: usize, n: u16, nn: u16, nnn: u16) ->
                 // 8XY4 - ADD VX, VY
                 4 => format!("ADD V{}, 0x{:04x}", x, y),
                 // Default
-                _ => format!(""),
+                _ => String::new(),
             }
         }
         // ANNN - set index register to NNN
         0xA000 => format!("LD I, 0x{:04x}", nnn),
         // DXYN - display/draw
         0xD000 => format!("DRW V{}, V{}, 0x{:04x}", x, y, n),
-        _ => format!(""),
+        _ => String::new(),
     }
     .to_string()
 }
 
 fn pause() {
     let mut stdout = stdout();
-    stdout.write(b"Press Enter to continue").unwrap();
+    stdout.write_all(b"Press Enter to continue").unwrap();
     stdout.flush().unwrap();
-    stdin().read(&mut [0]).unwrap();
+    // Any input or the end of it continues
+    stdin().read_exact(&mut [0]).ok();
 }
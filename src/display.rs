@@ -1,9 +1,45 @@
+use sdl2::pixels::PixelFormatEnum;
 use sdl2::rect::Rect;
+use sdl2::render::Texture;
 use sdl2::Sdl;
 use sdl2::{pixels::Color, EventPump};
-use sdl2::{render::Canvas, video::Window};
+use sdl2::{
+    render::Canvas,
+    video::{FullscreenType, Window},
+};
 
-use crate::{DISPLAY_HEIGHT, DISPLAY_LEN, DISPLAY_WIDTH};
+use crate::filter::{Filters, Frame};
+use crate::viewport::{self, Aspect, Scaling};
+use crate::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// Bytes per pixel of the streaming texture (RGB24)
+const BYTES_PER_PIXEL: usize = 3;
+
+// How lit pixels linger on screen, to hide the flicker of XOR-redrawn sprites
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Persistence {
+    // Pixels turn off immediately
+    Off,
+    // Pixels fade out linearly over the given number of frames
+    Fade(u32),
+    // Pixels lit in either of the last two frames are shown
+    Blend,
+}
+
+impl Persistence {
+    // Parses a persistence mode name ("off", "fade" or "blend")
+    pub fn parse(mode: &str, fade_frames: u32) -> Result<Self, String> {
+        match mode {
+            "off" => Ok(Persistence::Off),
+            "fade" => Ok(Persistence::Fade(fade_frames.max(1))),
+            "blend" => Ok(Persistence::Blend),
+            _ => Err(format!(
+                "Unknown persistence mode '{}', expected off, fade or blend",
+                mode
+            )),
+        }
+    }
+}
 
 pub struct Display {
     pub canvas: Canvas<Window>,
@@ -11,6 +47,18 @@ pub struct Display {
     pub scale: u32,
     pub fgcol: Color,
     pub bgcol: Color,
+    pub persistence: Persistence,
+    pub filters: Filters,
+    pub scaling: Scaling,
+    pub aspect: Aspect,
+
+    texture: Texture,             // Streaming texture holding the framebuffer
+    texture_size: (usize, usize), // Texture resolution, grows with filters
+    pixels: Vec<u8>,              // RGB staging buffer at native resolution
+    intensity: Vec<f32>,          // Per-pixel brightness, 0.0 (bg) to 1.0 (fg)
+    last_frame: Vec<u8>,          // Framebuffer of the previous frame
+    width: usize,                 // Framebuffer width
+    height: usize,                // Framebuffer height
 }
 
 impl Display {
@@ -20,69 +68,174 @@ impl Display {
         scale: u32,
         fg_col: (u8, u8, u8),
         bg_col: (u8, u8, u8),
+    ) -> Self {
+        Display::with_resolution(
+            sdl_context,
+            window_title,
+            DISPLAY_WIDTH,
+            DISPLAY_HEIGHT,
+            scale,
+            fg_col,
+            bg_col,
+        )
+    }
+
+    // Creates a display for a framebuffer of the given resolution
+    pub fn with_resolution(
+        sdl_context: &Sdl,
+        window_title: &str,
+        width: usize,
+        height: usize,
+        scale: u32,
+        fg_col: (u8, u8, u8),
+        bg_col: (u8, u8, u8),
     ) -> Self {
         let video_subsystem = sdl_context.video().unwrap();
         let event_pump = sdl_context.event_pump().unwrap();
         let window = video_subsystem
-            .window(
-                window_title,
-                DISPLAY_WIDTH as u32 * scale,
-                DISPLAY_HEIGHT as u32 * scale,
-            )
+            .window(window_title, width as u32 * scale, height as u32 * scale)
             .position_centered()
+            .resizable()
             .build()
             .unwrap();
 
         let canvas = window.into_canvas().build().unwrap();
 
+        // The renderer scales the texture up to the window size
+        let texture = canvas
+            .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+            .unwrap();
+
         Display {
             canvas,
             event_pump,
             scale,
             fgcol: Color::RGB(fg_col.0, fg_col.1, fg_col.2),
             bgcol: Color::RGB(bg_col.0, bg_col.1, bg_col.2),
+            persistence: Persistence::Off,
+            filters: Filters::default(),
+            scaling: Scaling::Fit,
+            aspect: Aspect::Native,
+            texture,
+            texture_size: (width, height),
+            pixels: vec![0; width * height * BYTES_PER_PIXEL],
+            intensity: vec![0.0; width * height],
+            last_frame: vec![0; width * height],
+            width,
+            height,
         }
     }
 
-    // Clears the display to black
-    pub fn clear(&mut self) {
-        self.canvas.set_draw_color(self.bgcol);
-        self.canvas.clear();
-        self.canvas.present();
+    // Whether pixels are still fading, so frames must be rendered even if
+    // the framebuffer did not change
+    pub fn is_fading(&self) -> bool {
+        match self.persistence {
+            Persistence::Off => false,
+            Persistence::Fade(_) => self.intensity.iter().any(|&i| i > 0.0 && i < 1.0),
+            Persistence::Blend => true,
+        }
     }
 
-    // Renders the given buffer to the display
-    pub fn render(&mut self, buffer: [u8; DISPLAY_LEN]) {
-        // Fill with buffer
-        let scl = self.scale as usize;
-        for x in 0..DISPLAY_WIDTH {
-            for y in 0..DISPLAY_HEIGHT {
-                if buffer[y * DISPLAY_WIDTH + x] > 0 {
-                    // Foreground
-                    self.canvas.set_draw_color(self.fgcol);
-                    self.canvas
-                        .fill_rect(Rect::new(
-                            (x * scl) as i32,
-                            (y * scl) as i32,
-                            scl as u32,
-                            scl as u32,
-                        ))
-                        .unwrap();
-                } else {
-                    // Background
-                    self.canvas.set_draw_color(self.bgcol);
-                    self.canvas
-                        .fill_rect(Rect::new(
-                            (x * scl) as i32,
-                            (y * scl) as i32,
-                            scl as u32,
-                            scl as u32,
-                        ))
-                        .unwrap();
+    // Renders the given buffer to the display and presents the frame
+    pub fn render(&mut self, buffer: &[u8]) {
+        // Apply persistence to get the brightness of each pixel
+        match self.persistence {
+            Persistence::Off => {
+                for (i, &on) in self.intensity.iter_mut().zip(buffer.iter()) {
+                    *i = if on > 0 { 1.0 } else { 0.0 };
                 }
             }
+            Persistence::Fade(frames) => {
+                let decay = 1.0 / frames as f32;
+                for (i, &on) in self.intensity.iter_mut().zip(buffer.iter()) {
+                    *i = if on > 0 { 1.0 } else { (*i - decay).max(0.0) };
+                }
+            }
+            Persistence::Blend => {
+                for ((i, last), &on) in self
+                    .intensity
+                    .iter_mut()
+                    .zip(self.last_frame.iter())
+                    .zip(buffer.iter())
+                {
+                    *i = if on > 0 || *last > 0 { 1.0 } else { 0.0 };
+                }
+            }
+        }
+        self.last_frame.copy_from_slice(buffer);
+
+        let (fg, bg) = (self.fgcol, self.bgcol);
+        for (pixel, &i) in self
+            .pixels
+            .chunks_exact_mut(BYTES_PER_PIXEL)
+            .zip(self.intensity.iter())
+        {
+            pixel.copy_from_slice(&[
+                blend(bg.r, fg.r, i),
+                blend(bg.g, fg.g, i),
+                blend(bg.b, fg.b, i),
+            ]);
         }
 
+        if self.filters.is_empty() {
+            self.upload(self.width, self.height, None);
+        } else {
+            let frame = Frame::new(self.width, self.height, self.pixels.clone());
+            let frame = self.filters.apply(frame, self.scale);
+            self.upload(frame.width, frame.height, Some(&frame.data));
+        }
+
+        // Black bars around the frame
+        let (x, y, w, h) = viewport::place(
+            self.canvas.output_size().unwrap(),
+            (self.width as u32, self.height as u32),
+            self.scaling,
+            self.aspect,
+        );
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas
+            .copy(&self.texture, None, Some(Rect::new(x, y, w, h)))
+            .unwrap();
         self.canvas.present();
     }
+
+    // Switches between a window and fullscreen on the desktop resolution
+    pub fn set_fullscreen(&mut self, fullscreen: bool) {
+        let mode = if fullscreen {
+            FullscreenType::Desktop
+        } else {
+            FullscreenType::Off
+        };
+        if let Err(e) = self.canvas.window_mut().set_fullscreen(mode) {
+            eprintln!("Could not change fullscreen mode: {}", e);
+        }
+    }
+
+    pub fn is_fullscreen(&self) -> bool {
+        self.canvas.window().fullscreen_state() != FullscreenType::Off
+    }
+
+    // Uploads RGB data to the texture, recreating it if the resolution
+    // changed. Uploads the native staging buffer if no data is given
+    fn upload(&mut self, width: usize, height: usize, data: Option<&[u8]>) {
+        if self.texture_size != (width, height) {
+            let texture = self
+                .canvas
+                .create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .unwrap();
+            let old = std::mem::replace(&mut self.texture, texture);
+            unsafe { old.destroy() };
+            self.texture_size = (width, height);
+        }
+        let data = data.unwrap_or(&self.pixels);
+        self.texture
+            .update(None, data, width * BYTES_PER_PIXEL)
+            .unwrap();
+    }
+}
+
+// Linear interpolation between two color channels
+fn blend(from: u8, to: u8, t: f32) -> u8 {
+    (from as f32 + (to as f32 - from as f32) * t).round() as u8
 }
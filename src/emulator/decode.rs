@@ -0,0 +1,166 @@
+// Instructions decoded once into an operation and its operands, so the
+// interpreter runs cached code with a single flat match
+
+use std::fmt::Display;
+
+use super::opcode_pattern;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Op {
+    Cls,    // 00E0 (any 0NN0)
+    Ret,    // 00EE (any 0NNE)
+    Jp,     // 1NNN
+    Call,   // 2NNN
+    SeImm,  // 3XNN
+    SneImm, // 4XNN
+    SeReg,  // 5XY0
+    LdImm,  // 6XNN
+    AddImm, // 7XNN
+    Ld,     // 8XY0
+    Or,     // 8XY1
+    And,    // 8XY2
+    Xor,    // 8XY3
+    Add,    // 8XY4
+    Sub,    // 8XY5
+    Shr,    // 8XY6
+    Subn,   // 8XY7
+    Shl,    // 8XYE
+    SneReg, // 9XY0
+    LdI,    // ANNN
+    JpV0,   // BNNN
+    Rnd,    // CXNN
+    Drw,    // DXYN
+    Skp,    // EX9E
+    Sknp,   // EXA1
+    LdVxDt, // FX07
+    LdKey,  // FX0A
+    LdDt,   // FX15
+    LdSt,   // FX18
+    AddI,   // FX1E
+    LdFont, // FX29
+    Bcd,    // FX33
+    Store,  // FX55
+    Load,   // FX65
+    Nop,    // Anything else
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Decoded {
+    pub instr: u16,
+    pub op: Op,
+    pub x: u8,
+    pub y: u8,
+    pub n: u8,
+    pub nn: u8,
+    pub nnn: u16,
+}
+
+pub fn decode(instr: u16) -> Decoded {
+    let n = (instr & 0x000F) as u8;
+    let nn = (instr & 0x00FF) as u8;
+    let op = match instr & 0xF000 {
+        0x0000 => match n {
+            0x0 => Op::Cls,
+            0xE => Op::Ret,
+            _ => Op::Nop,
+        },
+        0x1000 => Op::Jp,
+        0x2000 => Op::Call,
+        0x3000 => Op::SeImm,
+        0x4000 => Op::SneImm,
+        0x5000 => Op::SeReg,
+        0x6000 => Op::LdImm,
+        0x7000 => Op::AddImm,
+        0x8000 => match n {
+            0x0 => Op::Ld,
+            0x1 => Op::Or,
+            0x2 => Op::And,
+            0x3 => Op::Xor,
+            0x4 => Op::Add,
+            0x5 => Op::Sub,
+            0x6 => Op::Shr,
+            0x7 => Op::Subn,
+            0xE => Op::Shl,
+            _ => Op::Nop,
+        },
+        0x9000 => Op::SneReg,
+        0xA000 => Op::LdI,
+        0xB000 => Op::JpV0,
+        0xC000 => Op::Rnd,
+        0xD000 => Op::Drw,
+        0xE000 => match nn {
+            0x9E => Op::Skp,
+            0xA1 => Op::Sknp,
+            _ => Op::Nop,
+        },
+        _ => match nn {
+            0x07 => Op::LdVxDt,
+            0x0A => Op::LdKey,
+            0x15 => Op::LdDt,
+            0x18 => Op::LdSt,
+            0x1E => Op::AddI,
+            0x29 => Op::LdFont,
+            0x33 => Op::Bcd,
+            0x55 => Op::Store,
+            0x65 => Op::Load,
+            _ => Op::Nop,
+        },
+    };
+    Decoded {
+        instr,
+        op,
+        x: ((instr & 0x0F00) >> 8) as u8,
+        y: ((instr & 0x00F0) >> 4) as u8,
+        n,
+        nn,
+        nnn: instr & 0x0FFF,
+    }
+}
+
+// Disassembly, in Cowgod's mnemonics. Words that are no instruction are
+// shown as data, even though the interpreter runs some of them
+impl Display for Decoded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (x, y, nn, nnn) = (self.x, self.y, self.nn, self.nnn);
+        if opcode_pattern(self.instr).is_none() {
+            return write!(f, "DW 0x{:04X}", self.instr);
+        }
+        match &self.op {
+            Op::Cls => write!(f, "CLS"),
+            Op::Ret => write!(f, "RET"),
+            Op::Jp => write!(f, "JP 0x{:03X}", nnn),
+            Op::Call => write!(f, "CALL 0x{:03X}", nnn),
+            Op::SeImm => write!(f, "SE V{:X}, 0x{:02X}", x, nn),
+            Op::SneImm => write!(f, "SNE V{:X}, 0x{:02X}", x, nn),
+            Op::SeReg => write!(f, "SE V{:X}, V{:X}", x, y),
+            Op::LdImm => write!(f, "LD V{:X}, 0x{:02X}", x, nn),
+            Op::AddImm => write!(f, "ADD V{:X}, 0x{:02X}", x, nn),
+            Op::Ld => write!(f, "LD V{:X}, V{:X}", x, y),
+            Op::Or => write!(f, "OR V{:X}, V{:X}", x, y),
+            Op::And => write!(f, "AND V{:X}, V{:X}", x, y),
+            Op::Xor => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Op::Add => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Op::Sub => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Op::Shr => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Op::Subn => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Op::Shl => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Op::SneReg => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Op::LdI => write!(f, "LD I, 0x{:03X}", nnn),
+            Op::JpV0 => write!(f, "JP V0, 0x{:03X}", nnn),
+            Op::Rnd => write!(f, "RND V{:X}, 0x{:02X}", x, nn),
+            Op::Drw => write!(f, "DRW V{:X}, V{:X}, {}", x, y, self.n),
+            Op::Skp => write!(f, "SKP V{:X}", x),
+            Op::Sknp => write!(f, "SKNP V{:X}", x),
+            Op::LdVxDt => write!(f, "LD V{:X}, DT", x),
+            Op::LdKey => write!(f, "LD V{:X}, K", x),
+            Op::LdDt => write!(f, "LD DT, V{:X}", x),
+            Op::LdSt => write!(f, "LD ST, V{:X}", x),
+            Op::AddI => write!(f, "ADD I, V{:X}", x),
+            Op::LdFont => write!(f, "LD F, V{:X}", x),
+            Op::Bcd => write!(f, "LD B, V{:X}", x),
+            Op::Store => write!(f, "LD [I], V{:X}", x),
+            Op::Load => write!(f, "LD V{:X}, [I]", x),
+            Op::Nop => write!(f, "DW 0x{:04X}", self.instr),
+        }
+    }
+}
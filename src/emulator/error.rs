@@ -0,0 +1,26 @@
+use std::{error::Error, fmt::Display};
+
+pub type Chip8Result<T> = Result<T, Chip8Error>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Chip8Error {
+    PcOutOfBounds(usize),
+    StackOverflow(usize),
+    StackUnderflow(usize),
+}
+
+impl Error for Chip8Error {}
+
+impl Display for Chip8Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            Chip8Error::PcOutOfBounds(pc) => {
+                write!(f, "Program counter 0x{:03x} is outside of memory.", pc)
+            }
+            Chip8Error::StackOverflow(pc) => write!(f, "Stack overflow at 0x{:03x}.", pc),
+            Chip8Error::StackUnderflow(pc) => {
+                write!(f, "Return with an empty stack at 0x{:03x}.", pc)
+            }
+        }
+    }
+}
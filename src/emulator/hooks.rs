@@ -0,0 +1,135 @@
+use super::Chip8;
+
+// Callbacks letting tools observe and modify the machine as it runs. Hooks
+// get the machine itself, so they can read and change any state. Hooks
+// registered while hooks run are kept, but first called at the next event.
+
+// Called with the fetched instruction. Before-instruction hooks run with
+// the program counter still pointing at it
+pub type InstructionHook = Box<dyn FnMut(&mut Chip8, u16)>;
+// Called with the address and value of each byte the program writes
+pub type MemoryWriteHook = Box<dyn FnMut(&mut Chip8, usize, u8)>;
+// Called after a sprite is drawn with its position and height
+pub type DrawHook = Box<dyn FnMut(&mut Chip8, usize, usize, usize)>;
+// Called once per 60 Hz frame, or when the beep starts or stops
+pub type EventHook = Box<dyn FnMut(&mut Chip8)>;
+
+#[derive(Default)]
+pub struct Hooks {
+    before_instruction: Vec<InstructionHook>,
+    after_instruction: Vec<InstructionHook>,
+    memory_write: Vec<MemoryWriteHook>,
+    draw: Vec<DrawHook>,
+    frame: Vec<EventHook>,
+    sound_start: Vec<EventHook>,
+    sound_stop: Vec<EventHook>,
+    cleared: bool, // Set by clear_hooks, so hooks running are dropped too
+}
+
+impl Hooks {
+    // Moves hooks registered while the hooks in `self` were running
+    // behind them
+    fn merge(&mut self, mut added: Hooks) {
+        self.before_instruction
+            .append(&mut added.before_instruction);
+        self.after_instruction.append(&mut added.after_instruction);
+        self.memory_write.append(&mut added.memory_write);
+        self.draw.append(&mut added.draw);
+        self.frame.append(&mut added.frame);
+        self.sound_start.append(&mut added.sound_start);
+        self.sound_stop.append(&mut added.sound_stop);
+    }
+}
+
+impl Chip8 {
+    pub fn on_before_instruction<F: FnMut(&mut Chip8, u16) + 'static>(&mut self, hook: F) {
+        self.hooks.before_instruction.push(Box::new(hook));
+    }
+
+    pub fn on_after_instruction<F: FnMut(&mut Chip8, u16) + 'static>(&mut self, hook: F) {
+        self.hooks.after_instruction.push(Box::new(hook));
+    }
+
+    pub fn on_memory_write<F: FnMut(&mut Chip8, usize, u8) + 'static>(&mut self, hook: F) {
+        self.hooks.memory_write.push(Box::new(hook));
+    }
+
+    pub fn on_draw<F: FnMut(&mut Chip8, usize, usize, usize) + 'static>(&mut self, hook: F) {
+        self.hooks.draw.push(Box::new(hook));
+    }
+
+    pub fn on_frame<F: FnMut(&mut Chip8) + 'static>(&mut self, hook: F) {
+        self.hooks.frame.push(Box::new(hook));
+    }
+
+    pub fn on_sound_start<F: FnMut(&mut Chip8) + 'static>(&mut self, hook: F) {
+        self.hooks.sound_start.push(Box::new(hook));
+    }
+
+    pub fn on_sound_stop<F: FnMut(&mut Chip8) + 'static>(&mut self, hook: F) {
+        self.hooks.sound_stop.push(Box::new(hook));
+    }
+
+    // Removes all hooks
+    pub fn clear_hooks(&mut self) {
+        self.hooks = Hooks {
+            cleared: true,
+            ..Hooks::default()
+        };
+    }
+
+    // Whether hooks need to see each instruction
+    pub(super) fn has_instruction_hooks(&self) -> bool {
+        !self.hooks.before_instruction.is_empty() || !self.hooks.after_instruction.is_empty()
+    }
+
+    // Runs the hooks selected by `list`. They are taken out of the machine
+    // while they run, so they can borrow it mutably
+    fn call_hooks<H, F>(&mut self, list: fn(&mut Hooks) -> &mut Vec<H>, mut call: F)
+    where
+        F: FnMut(&mut H, &mut Chip8),
+    {
+        if list(&mut self.hooks).is_empty() {
+            return;
+        }
+        let mut hooks = std::mem::take(&mut self.hooks);
+        for hook in list(&mut hooks).iter_mut() {
+            call(hook, self);
+        }
+        let mut added = std::mem::replace(&mut self.hooks, hooks);
+        if added.cleared {
+            added.cleared = false;
+            self.hooks = added;
+        } else {
+            self.hooks.merge(added);
+        }
+    }
+
+    pub(super) fn before_instruction_hooks(&mut self, instr: u16) {
+        self.call_hooks(|h| &mut h.before_instruction, |hook, c| hook(c, instr));
+    }
+
+    pub(super) fn after_instruction_hooks(&mut self, instr: u16) {
+        self.call_hooks(|h| &mut h.after_instruction, |hook, c| hook(c, instr));
+    }
+
+    pub(super) fn memory_write_hooks(&mut self, addr: usize, value: u8) {
+        self.call_hooks(|h| &mut h.memory_write, |hook, c| hook(c, addr, value));
+    }
+
+    pub(super) fn draw_hooks(&mut self, x: usize, y: usize, height: usize) {
+        self.call_hooks(|h| &mut h.draw, |hook, c| hook(c, x, y, height));
+    }
+
+    pub(super) fn frame_hooks(&mut self) {
+        self.call_hooks(|h| &mut h.frame, |hook, c| hook(c));
+    }
+
+    pub(super) fn sound_hooks(&mut self, started: bool) {
+        if started {
+            self.call_hooks(|h| &mut h.sound_start, |hook, c| hook(c));
+        } else {
+            self.call_hooks(|h| &mut h.sound_stop, |hook, c| hook(c));
+        }
+    }
+}
@@ -0,0 +1,753 @@
+mod decode;
+mod error;
+mod hooks;
+mod quirks;
+mod threaded;
+
+use crate::{
+    cheat::Cheats,
+    debug,
+    keyboard::{key_mask, Keypad},
+    symbols::Symbols,
+    time,
+};
+
+pub use decode::{decode, Decoded, Op};
+pub use error::{Chip8Error, Chip8Result};
+pub use hooks::{DrawHook, EventHook, Hooks, InstructionHook, MemoryWriteHook};
+pub use quirks::{Addressing, KeyWait, Quirks};
+pub use threaded::Backend;
+
+use crate::{
+    DISPLAY_HEIGHT, DISPLAY_LEN, DISPLAY_WIDTH, FRAME_TIME_NS, MAX_ROM_SIZE, NUM_REGISTERS,
+    PROGRAM_LOC, RAM_SIZE, STACK_SIZE,
+};
+
+// Whether the machine runs freely or sits on an FX0A
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Status {
+    Running,
+    // FX0A runs again until it takes a key, while timers keep ticking.
+    // `held` has the keys that were down when it last ran
+    WaitingForKey { held: u16 },
+}
+
+pub struct Chip8 {
+    pub registers: [u8; NUM_REGISTERS],
+    pub index: u16, // Register index
+    pub ram: [u8; RAM_SIZE],
+    pub stack: [u16; STACK_SIZE],
+    pub istack: usize,              // Index top of the stack
+    pub pc: usize,                  // Program counter
+    pub dt: u8,                     // Delay timer
+    pub st: u8,                     // Sound timer
+    pub display: [u8; DISPLAY_LEN], // Display memory
+    pub display_update_flag: bool,  // Update display flag
+    pub display_clear_flag: bool,   // Clear display flag
+    pub beep_flag: bool,            // Beep flag
+    pub quirks: Quirks,             // Interpreter specific behaviors
+    pub cheats: Cheats,             // Values frozen every frame
+    pub backend: Backend,           // Engine running the instructions
+    pub status: Status,             // Set while FX0A waits for a key
+    pub symbols: Symbols,           // Labels and source lines shown by the debugger
+
+    instruction_time_ns: u128,                    // Emulation speed (ns)
+    debug_mode: bool,                             // Debug mode flag
+    last_timer_t: u128,                           // Last timer time
+    last_instruction_t: u128,                     // Last instruction time
+    frame_budget_ns: u128,                        // Instruction time left over from the last frame
+    rng: u64,                                     // Random number generator state
+    instructions: u64,                            // Instructions fetched since start
+    code_cache: Box<[Option<Decoded>; RAM_SIZE]>, // Decoded instruction at each address
+    blocks: threaded::Blocks,                     // Compiled code of the threaded backend
+    hooks: Hooks,                                 // Tool callbacks
+}
+
+impl Chip8 {
+    pub fn new(
+        rom: Vec<u8>,
+        fonts: [u8; 80],
+        start_t: u128,
+        instruction_time_ns: u128,
+        debug_mode: bool,
+    ) -> Self {
+        let mut ram = [0; RAM_SIZE];
+
+        // Copy fonts into memory
+        ram[..80].copy_from_slice(&fonts);
+
+        // Copy ROM into memory, anything past the end of RAM is dropped
+        let bytes = rom.len().min(MAX_ROM_SIZE);
+        let ppos = PROGRAM_LOC + bytes;
+        ram[PROGRAM_LOC..ppos].copy_from_slice(&rom[0..bytes]);
+
+        Chip8 {
+            ram,
+            registers: [0; NUM_REGISTERS],
+            index: 0,
+            stack: [0; STACK_SIZE],
+            istack: 0,
+            pc: PROGRAM_LOC,
+            dt: 0,
+            st: 0,
+            display: [0; DISPLAY_LEN],
+            display_update_flag: false,
+            display_clear_flag: false,
+            beep_flag: false,
+            quirks: Quirks::default(),
+            cheats: Cheats::default(),
+            backend: Backend::default(),
+            status: Status::Running,
+            symbols: Symbols::default(),
+            instruction_time_ns,
+            debug_mode,
+            last_timer_t: start_t,
+            last_instruction_t: start_t,
+            frame_budget_ns: 0,
+            rng: time::time_nanos() as u64,
+            instructions: 0,
+            code_cache: Box::new([None; RAM_SIZE]),
+            blocks: threaded::Blocks::default(),
+            hooks: Hooks::default(),
+        }
+    }
+
+    // Seeds the random number generator, for reproducible runs
+    pub fn seed(&mut self, seed: u64) {
+        self.rng = seed;
+    }
+
+    // Random number generator state, so peers can compare it
+    pub fn rng_state(&self) -> u64 {
+        self.rng
+    }
+
+    // Restarts the machine with a ROM. Settings, cheats, symbols and hooks
+    // are kept, everything else starts over like in a new machine
+    pub fn reset(&mut self, rom: Vec<u8>, fonts: [u8; 80]) {
+        let fresh = Chip8::new(rom, fonts, 0, self.instruction_time_ns, self.debug_mode);
+        let old = std::mem::replace(self, fresh);
+        self.quirks = old.quirks;
+        self.cheats = old.cheats;
+        self.backend = old.backend;
+        self.symbols = old.symbols;
+        self.hooks = old.hooks;
+    }
+
+    // Number of instructions run since the machine was created
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    // Runs a clock cycle
+    pub fn cycle(&mut self, t: u128, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        self.display_update_flag = false;
+        self.display_clear_flag = false;
+
+        // Decrement delay_timer and sound_timer 60 times per second
+        if t - self.last_timer_t > FRAME_TIME_NS {
+            self.end_frame();
+            self.last_timer_t = t;
+        }
+
+        if t - self.last_instruction_t > self.instruction_time_ns {
+            self.step(keypad)?;
+            self.last_instruction_t = t;
+        }
+        Ok(())
+    }
+
+    // Runs one 60 Hz frame as fast as possible: the instructions executed in
+    // a frame at the configured speed, then a timer tick
+    pub fn run_frame(&mut self, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        self.display_update_flag = false;
+        self.display_clear_flag = false;
+
+        self.frame_budget_ns += FRAME_TIME_NS;
+        let count = self.frame_budget_ns / self.instruction_time_ns;
+        self.frame_budget_ns -= count * self.instruction_time_ns;
+        self.run_instructions(count, keypad)?;
+        self.end_frame();
+        Ok(())
+    }
+
+    // Runs once per 60 Hz frame: ticks the timers, applies cheats and runs
+    // the frame hooks
+    fn end_frame(&mut self) {
+        let beeping = self.beep_flag;
+        self.tick_timers();
+        if !self.cheats.freezes.is_empty() {
+            let cheats = std::mem::take(&mut self.cheats);
+            cheats.apply(self);
+            self.cheats = cheats;
+        }
+        if self.beep_flag != beeping {
+            self.sound_hooks(self.beep_flag);
+        }
+        self.frame_hooks();
+    }
+
+    // Decrements the delay and sound timers if their value is > 0
+    pub fn tick_timers(&mut self) {
+        if self.dt > 0 {
+            self.dt -= 1;
+        }
+        if self.st > 0 {
+            self.st -= 1;
+            self.beep_flag = self.st > 0;
+        }
+    }
+
+    // Fetches and runs a single instruction
+    pub fn step(&mut self, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        self.run_instructions(1, keypad)
+    }
+
+    // Runs a number of instructions with the selected backend. The
+    // debugger steps through the interpreter
+    fn run_instructions(&mut self, count: u128, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        if self.backend == Backend::Threaded && !self.debug_mode {
+            return self.run_threaded(count, keypad);
+        }
+        self.interpret_instructions(count, keypad)
+    }
+
+    // Interprets a number of instructions in one loop. Instructions are
+    // decoded once per address and cached until the code changes
+    fn interpret_instructions(&mut self, count: u128, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        for _ in 0..count {
+            if self.pc + 1 >= RAM_SIZE {
+                return Err(Chip8Error::PcOutOfBounds(self.pc));
+            }
+            // RUN INSTRUCTION
+            let decoded = match self.code_cache[self.pc] {
+                Some(decoded) => decoded,
+                None => {
+                    let instr = ((self.ram[self.pc] as u16) << 8) | self.ram[self.pc + 1] as u16;
+                    let decoded = decode(instr);
+                    self.code_cache[self.pc] = Some(decoded);
+                    decoded
+                }
+            };
+            let instr = decoded.instr;
+            self.before_instruction_hooks(instr);
+            self.pc += 2;
+            self.instructions += 1;
+
+            if self.debug_mode {
+                debug::debug(self.pc, instr, self.registers, self.index, &self.symbols);
+            }
+
+            self.interpret(keypad, decoded)?;
+            self.after_instruction_hooks(instr);
+        }
+        Ok(())
+    }
+
+    // Reads a byte of memory. Addresses wrap around the end of RAM
+    fn read_mem(&self, addr: usize) -> u8 {
+        self.ram[addr % RAM_SIZE]
+    }
+
+    // Writes a byte of memory. Addresses wrap around the end of RAM
+    fn write_mem(&mut self, addr: usize, value: u8) {
+        self.ram[addr % RAM_SIZE] = value;
+        self.invalidate_code(addr % RAM_SIZE);
+        self.memory_write_hooks(addr % RAM_SIZE, value);
+    }
+
+    // Writes memory from outside the program, e.g. from a debugger or a
+    // saved state. Tools must write through here rather than to `ram`, so
+    // code they change is decoded again. Addresses wrap around the end of
+    // RAM and no memory write hooks run
+    pub fn write_ram(&mut self, addr: usize, data: &[u8]) {
+        for (i, &value) in data.iter().enumerate() {
+            let addr = (addr + i) % RAM_SIZE;
+            self.ram[addr] = value;
+            self.code_cache[addr] = None;
+            self.code_cache[(addr + RAM_SIZE - 1) % RAM_SIZE] = None;
+            self.blocks.forget(addr);
+        }
+    }
+
+    // Drops the decoded instructions that include the byte at `addr`
+    fn invalidate_code(&mut self, addr: usize) {
+        self.code_cache[addr] = None;
+        self.code_cache[(addr + RAM_SIZE - 1) % RAM_SIZE] = None;
+        self.blocks.invalidate(addr);
+    }
+
+    // Next byte of the xorshift64* generator
+    fn random_byte(&mut self) -> u8 {
+        // Zero is a fixed point of xorshift
+        if self.rng == 0 {
+            self.rng = 0x9E37_79B9_7F4A_7C15;
+        }
+        self.rng ^= self.rng >> 12;
+        self.rng ^= self.rng << 25;
+        self.rng ^= self.rng >> 27;
+        (self.rng.wrapping_mul(0x2545_F491_4F6C_DD1D) >> 56) as u8
+    }
+
+    #[inline(always)]
+    fn interpret(&mut self, keypad: &mut dyn Keypad, decoded: Decoded) -> Chip8Result<()> {
+        let Decoded { op, nn, nnn, .. } = decoded;
+        let (x, y, n) = (decoded.x as usize, decoded.y as usize, decoded.n as usize);
+        match op {
+            // 00E0 - CLS
+            Op::Cls => {
+                self.display.iter_mut().for_each(|m| *m = 0);
+                self.display_clear_flag = true;
+            }
+            // 00EE - RET
+            Op::Ret => {
+                if self.istack == 0 {
+                    return Err(Chip8Error::StackUnderflow(self.pc - 2));
+                }
+                self.pc = self.stack[self.istack] as usize;
+                self.istack -= 1;
+            }
+            // 1NNN - JMP
+            Op::Jp => self.pc = nnn as usize,
+            // 2NNN - CALL NNN
+            Op::Call => {
+                if self.istack + 1 >= STACK_SIZE {
+                    return Err(Chip8Error::StackOverflow(self.pc - 2));
+                }
+                self.istack += 1;
+                self.stack[self.istack] = self.pc as u16;
+                self.pc = nnn as usize;
+            }
+            // 3XNN - SE VX, NN
+            Op::SeImm => {
+                if self.registers[x] == nn {
+                    self.pc += 2;
+                }
+            }
+            // 4XNN - SNE VX, NN
+            Op::SneImm => {
+                if self.registers[x] != nn {
+                    self.pc += 2;
+                }
+            }
+            // 5XY0 - SE VX, VY
+            Op::SeReg => {
+                if self.registers[x] == self.registers[y] {
+                    self.pc += 2;
+                }
+            }
+            // 6XNN - LD  VX, NN
+            Op::LdImm => self.registers[x] = nn,
+            // 7XNN - ADD  VX, NN
+            Op::AddImm => self.registers[x] = self.registers[x].wrapping_add(nn),
+            // 8XY0 - LD VX, VY
+            Op::Ld => self.registers[x] = self.registers[y],
+            // 8XY1 - OR VX, VY
+            Op::Or => self.registers[x] |= self.registers[y],
+            // 8XY2 - AND VX, VY
+            Op::And => self.registers[x] &= self.registers[y],
+            // 8XY3 - XOR VX, VY
+            Op::Xor => self.registers[x] ^= self.registers[y],
+            // 8XY4 - ADD VX, VY
+            Op::Add => {
+                let res = self.registers[x] as usize + self.registers[y] as usize;
+                if res > 255 {
+                    // Carry to VF
+                    self.registers[0x0F] = 1;
+                } else {
+                    self.registers[0x0F] = 0;
+                }
+                self.registers[x] = res as u8;
+            }
+            // 8XY5 - SUB VX, VY
+            Op::Sub => {
+                self.registers[0x0F] = if self.registers[x] > self.registers[y] {
+                    // Carry to VF
+                    1
+                } else {
+                    0
+                };
+                self.registers[x] = (self.registers[x] as i32 - self.registers[y] as i32) as u8;
+            }
+            // 8XY6 - SHR VX {, VY}
+            Op::Shr => {
+                self.registers[0x0F] = self.registers[x] & 0x01;
+                self.registers[x] /= 2;
+            }
+            // 8XY7 - SUBN VX, VY
+            Op::Subn => {
+                self.registers[0x0F] = if self.registers[y] > self.registers[x] {
+                    1
+                } else {
+                    0
+                };
+                self.registers[x] = self.registers[y].wrapping_sub(self.registers[x]);
+            }
+            // 8XYE - SHL VX {, VY}
+            Op::Shl => {
+                self.registers[0x0F] = self.registers[x] & 0x80;
+                self.registers[x] = (self.registers[x] as u16 * 2) as u8;
+            }
+            // 0x9XY0 - SNE VX, VY  (skip next instruction)
+            Op::SneReg => {
+                if self.registers[x] != self.registers[y] {
+                    self.pc += 2;
+                }
+            }
+            // ANNN - LD  I, NNN
+            Op::LdI => self.index = nnn,
+            // BNNN - JMP  V0, NNN  (jump to nnn + V0)
+            Op::JpV0 => self.pc = nnn as usize + self.registers[0] as usize,
+            // CXNN - RND VX, NN  (set VX = RANDOM_BYTE AND NN)
+            Op::Rnd => self.registers[x] = nn & self.random_byte(),
+            // DXYN - DRW  VX, VY, N
+            Op::Drw => self.draw(x, y, n),
+            // EX9E - SKP VX  (skip next instr if key with val VX is pressed)
+            Op::Skp => {
+                if keypad.is_pressed(self.registers[x] & 0x0F) {
+                    self.pc += 2;
+                }
+            }
+            // EXA1 - SKNP VX  (skip next instr if key with val VX is not pressed)
+            Op::Sknp => {
+                if !keypad.is_pressed(self.registers[x] & 0x0F) {
+                    self.pc += 2;
+                }
+            }
+            // FX07 - LD VX, DT  (set VX = delay timer)
+            Op::LdVxDt => self.registers[x] = self.dt,
+            // FX0A - LD VX, K  (wait for a key, store its value in VX)
+            Op::LdKey => {
+                let held = key_mask(keypad);
+                let changed = match (self.status, self.quirks.key_wait) {
+                    (Status::Running, _) => 0,
+                    (Status::WaitingForKey { held: before }, KeyWait::Press) => held & !before,
+                    (Status::WaitingForKey { held: before }, KeyWait::Release) => before & !held,
+                };
+                if changed != 0 {
+                    self.registers[x] = changed.trailing_zeros() as u8;
+                    self.status = Status::Running;
+                } else {
+                    // No key yet, run this instruction again
+                    self.status = Status::WaitingForKey { held };
+                    self.pc -= 2;
+                }
+            }
+            // FX15 - LD DT, VX  (set delay timer = VX)
+            Op::LdDt => self.dt = self.registers[x],
+            // FX18 - LD ST, VX  (set sound timer = VX)
+            Op::LdSt => self.st = self.registers[x],
+            // FX1E - ADD I, VX
+            Op::AddI => {
+                let addr = self.index as u32 + self.registers[x] as u32;
+                if self.quirks.index_overflow {
+                    self.registers[0x0F] = if addr > 0x0FFF { 1 } else { 0 };
+                }
+                self.index = self.quirks.addressing.wrap(addr);
+            }
+            // FX29 - LD F, VX  (set I to location of sprite for digit VX)
+            Op::LdFont => self.index = self.registers[x] as u16 * 0x05,
+            // FX33 - LD B, VX  (store BCD representation of VX in I, I+1 and I+2)
+            Op::Bcd => self.store_bcd(x),
+            // FX55 - LD [I], VX  (set memory starting at I to values in V0 to VX)
+            Op::Store => self.store_registers(x),
+            // FX65 - LD VX, [I]  (set registers V0 to VX to memory starting at I)
+            Op::Load => {
+                for reg in 0..x + 1 {
+                    self.registers[reg] = self.read_mem(self.index as usize + reg);
+                }
+            }
+            // Unknown instructions do nothing
+            Op::Nop => (),
+        };
+        Ok(())
+    }
+
+    // DXYN - draws the N byte sprite at I at (VX, VY), setting VF on collision
+    fn draw(&mut self, x: usize, y: usize, n: usize) {
+        self.registers[0x0F] = 0;
+        let xpos: usize = self.registers[x] as usize % DISPLAY_WIDTH;
+        let ypos: usize = self.registers[y] as usize % DISPLAY_HEIGHT;
+        for row in 0..n {
+            // Fetch bits
+            let bits: u8 = self.read_mem(self.index as usize + row);
+            // Current Y
+            let cy = (ypos + row) % DISPLAY_HEIGHT;
+            // Loop over bits
+            for col in 0..8_usize {
+                // Current X
+                let cx = (xpos + col) % DISPLAY_WIDTH;
+                let current_color = self.display[cy * DISPLAY_WIDTH + cx];
+                let mask: u8 = 0x80 >> col;
+                let color = bits & mask;
+                // XOR
+                // 0 0 -> 0
+                // 0 1 -> 1
+                // 1 0 -> 1
+                // 1 1 -> 0
+                if color > 0 {
+                    // color is on
+                    if current_color > 0 {
+                        // current color is on
+                        self.display[cy * DISPLAY_WIDTH + cx] = 0;
+                        self.registers[0x0F] = 1;
+                    } else {
+                        // current color is off
+                        self.display[cy * DISPLAY_WIDTH + cx] = 1;
+                    }
+                } else {
+                    // Bit is off
+                    // Do nothing
+                }
+                if cx == DISPLAY_WIDTH - 1 {
+                    // Reached the right edge
+                    break;
+                }
+            }
+            if cy == DISPLAY_HEIGHT - 1 {
+                // Reached the bottom edge
+                break;
+            }
+        }
+        self.display_update_flag = true;
+        self.draw_hooks(xpos, ypos, n);
+    }
+
+    // FX33 - stores the BCD representation of VX in I, I+1 and I+2
+    fn store_bcd(&mut self, x: usize) {
+        let num = self.registers[x];
+        let h = num / 100;
+        let t = (num - h * 100) / 10;
+        let o = num - h * 100 - t * 10;
+        let i = self.index as usize;
+        self.write_mem(i, h);
+        self.write_mem(i + 1, t);
+        self.write_mem(i + 2, o);
+    }
+
+    // FX55 - stores V0 to VX in memory starting at I
+    fn store_registers(&mut self, x: usize) {
+        for reg in 0..x + 1 {
+            self.write_mem(self.index as usize + reg, self.registers[reg]);
+        }
+    }
+}
+
+// Whether an instruction is one the interpreter implements. Anything else,
+// including 0NNN machine code calls, runs as a no-op
+pub fn is_known_opcode(instr: u16) -> bool {
+    opcode_pattern(instr).is_some()
+}
+
+// The pattern of a known instruction, e.g. "8XY4" for 0x8124
+pub fn opcode_pattern(instr: u16) -> Option<&'static str> {
+    let pattern = match instr & 0xF000 {
+        0x0000 => match instr {
+            0x00E0 => "00E0",
+            0x00EE => "00EE",
+            _ => return None,
+        },
+        0x1000 => "1NNN",
+        0x2000 => "2NNN",
+        0x3000 => "3XNN",
+        0x4000 => "4XNN",
+        0x5000 if instr & 0x000F == 0 => "5XY0",
+        0x6000 => "6XNN",
+        0x7000 => "7XNN",
+        0x8000 => match instr & 0x000F {
+            0x0 => "8XY0",
+            0x1 => "8XY1",
+            0x2 => "8XY2",
+            0x3 => "8XY3",
+            0x4 => "8XY4",
+            0x5 => "8XY5",
+            0x6 => "8XY6",
+            0x7 => "8XY7",
+            0xE => "8XYE",
+            _ => return None,
+        },
+        0x9000 if instr & 0x000F == 0 => "9XY0",
+        0xA000 => "ANNN",
+        0xB000 => "BNNN",
+        0xC000 => "CXNN",
+        0xD000 => "DXYN",
+        0xE000 => match instr & 0x00FF {
+            0x9E => "EX9E",
+            0xA1 => "EXA1",
+            _ => return None,
+        },
+        0xF000 => match instr & 0x00FF {
+            0x07 => "FX07",
+            0x0A => "FX0A",
+            0x15 => "FX15",
+            0x18 => "FX18",
+            0x1E => "FX1E",
+            0x29 => "FX29",
+            0x33 => "FX33",
+            0x55 => "FX55",
+            0x65 => "FX65",
+            _ => return None,
+        },
+        _ => return None,
+    };
+    Some(pattern)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{headless::ScriptedKeypad, FONTS};
+
+    // Runs a program until it errors or has run the given number of steps
+    fn run(program: &[u16], steps: usize) -> Chip8 {
+        let rom = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new(rom, FONTS, 0, 1, false);
+        let mut keypad = ScriptedKeypad::default();
+        for _ in 0..steps {
+            if chip8.step(&mut keypad).is_err() {
+                break;
+            }
+        }
+        chip8
+    }
+
+    #[test]
+    fn subn_wraps() {
+        // V0 = 5, V1 = 3, V0 = V1 - V0
+        let chip8 = run(&[0x6005, 0x6103, 0x8017], 3);
+        assert_eq!(chip8.registers[0], 0xFE);
+        assert_eq!(chip8.registers[0x0F], 0);
+    }
+
+    #[test]
+    fn index_wraps_around_memory() {
+        // I = 0xFFF, then BCD, load, store and draw at the end of memory
+        // and I += V0
+        let chip8 = run(&[0xAFFF, 0x60FF, 0xF033, 0xF265, 0xF255, 0xD01F, 0xF01E], 7);
+        assert_eq!(chip8.registers[..3], [2, 5, 5]);
+        assert_eq!(chip8.ram[0x000], 5);
+        assert_eq!(chip8.index, 0x001);
+    }
+
+    #[test]
+    fn index_overflow_quirks() {
+        // I = 0xFFF, V0 = 2, I += V0
+        let program: [u16; 3] = [0xAFFF, 0x6002, 0xF01E];
+        let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let mut keypad = ScriptedKeypad::default();
+
+        let mut chip8 = Chip8::new(rom.clone(), FONTS, 0, 1, false);
+        chip8.quirks = Quirks {
+            addressing: Addressing::Bits16,
+            index_overflow: true,
+            ..Quirks::default()
+        };
+        for _ in 0..program.len() {
+            chip8.step(&mut keypad).unwrap();
+        }
+        assert_eq!(chip8.index, 0x1001);
+        assert_eq!(chip8.registers[0x0F], 1);
+
+        let mut chip8 = Chip8::new(rom, FONTS, 0, 1, false);
+        for _ in 0..program.len() {
+            chip8.step(&mut keypad).unwrap();
+        }
+        assert_eq!(chip8.index, 0x001);
+        assert_eq!(chip8.registers[0x0F], 0);
+    }
+
+    #[test]
+    fn key_wait_keeps_timers_running() {
+        use crate::keyboard::MaskKeypad;
+
+        // DT = 60, wait for a key into V1
+        let program: [u16; 3] = [0x603C, 0xF015, 0xF10A];
+        let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        for &(key_wait, taken_at) in &[(KeyWait::Release, 2), (KeyWait::Press, 1)] {
+            let mut chip8 = Chip8::new(rom.clone(), FONTS, 0, 1_000_000, false);
+            chip8.quirks.key_wait = key_wait;
+            // Key 5 already held when FX0A starts, then key 7 pressed and
+            // released
+            let frames = [1 << 5, 1 << 5, 1 << 5 | 1 << 7, 1 << 7, 0];
+            for (frame, &mask) in frames.iter().enumerate() {
+                chip8.run_frame(&mut MaskKeypad(mask)).unwrap();
+                if frame < taken_at {
+                    assert_eq!(chip8.pc, 0x204);
+                    assert!(chip8.status != Status::Running);
+                }
+            }
+            assert_eq!(chip8.status, Status::Running);
+            assert_eq!(chip8.dt, 60 - 5);
+            let expected = if key_wait == KeyWait::Release { 5 } else { 7 };
+            assert_eq!(chip8.registers[1], expected);
+        }
+    }
+
+    #[test]
+    fn hooks_observe_and_modify() {
+        use std::{cell::RefCell, rc::Rc};
+
+        // I = 0x300, V0 = 7, store V0, draw
+        let program: [u16; 4] = [0xA300, 0x6007, 0xF055, 0xD001];
+        let rom = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new(rom, FONTS, 0, 1, false);
+        let events = Rc::new(RefCell::new(Vec::new()));
+
+        let log = events.clone();
+        chip8.on_before_instruction(move |c, instr| {
+            log.borrow_mut().push(format!("{:03x} {:04x}", c.pc, instr))
+        });
+        let log = events.clone();
+        chip8.on_memory_write(move |c, addr, value| {
+            log.borrow_mut()
+                .push(format!("write {:03x} {}", addr, value));
+            // Hooks can change the machine
+            c.registers[1] = value * 2;
+        });
+        let log = events.clone();
+        chip8.on_draw(move |_, x, y, height| {
+            log.borrow_mut()
+                .push(format!("draw {} {} {}", x, y, height))
+        });
+
+        let mut keypad = ScriptedKeypad::default();
+        for _ in 0..program.len() {
+            chip8.step(&mut keypad).unwrap();
+        }
+        assert_eq!(
+            *events.borrow(),
+            [
+                "200 a300",
+                "202 6007",
+                "204 f055",
+                "write 300 7",
+                "206 d001",
+                "draw 7 7 1",
+            ]
+        );
+        assert_eq!(chip8.registers[1], 14);
+    }
+
+    #[test]
+    fn self_modifying_code_is_decoded_again() {
+        // Run V1 += 1 at 0x208, patch it to V1 += 5 with FX55 and run it
+        // again, then halt
+        let program = [
+            0x6005, 0xA209, 0x1208, 0x1206, 0x7101, 0x3101, 0x1206, 0xF055, 0x1208,
+        ];
+        let mut chip8 = run(&program, 12);
+        assert_eq!(chip8.registers[1], 6);
+
+        // Tool writes are seen too
+        chip8.write_ram(0x209, &[0x10]);
+        chip8.pc = 0x208;
+        chip8.step(&mut ScriptedKeypad::default()).unwrap();
+        assert_eq!(chip8.registers[1], 0x16);
+    }
+
+    #[test]
+    fn oversized_rom_is_truncated() {
+        let chip8 = Chip8::new(vec![0x12; RAM_SIZE], FONTS, 0, 1, false);
+        assert_eq!(chip8.ram[RAM_SIZE - 1], 0x12);
+    }
+}
@@ -0,0 +1,79 @@
+// Behaviors that differ between CHIP-8 interpreters
+
+// Width of the I register
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Addressing {
+    // I is masked to 12 bits, wrapping at 0xFFF like the original interpreters
+    Wrap12,
+    // I holds 16 bits (XO-CHIP). RAM is 4 KiB, so accesses past its end
+    // wrap around to the start of memory
+    Bits16,
+}
+
+impl Addressing {
+    // Parses an addressing mode name ("12bit" or "16bit")
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "12bit" => Ok(Addressing::Wrap12),
+            "16bit" => Ok(Addressing::Bits16),
+            _ => Err(format!(
+                "Unknown addressing mode '{}', expected 12bit or 16bit",
+                mode
+            )),
+        }
+    }
+
+    // Largest value the I register can hold
+    pub fn max_index(self) -> u16 {
+        match self {
+            Addressing::Wrap12 => 0x0FFF,
+            Addressing::Bits16 => 0xFFFF,
+        }
+    }
+
+    // Wraps an address to the width of the I register
+    pub fn wrap(self, addr: u32) -> u16 {
+        (addr & self.max_index() as u32) as u16
+    }
+}
+
+// When FX0A takes the key it waits for
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyWait {
+    // As soon as a key goes down
+    Press,
+    // When a key that was down is released, like the COSMAC VIP
+    Release,
+}
+
+impl KeyWait {
+    // Parses a key wait mode name ("press" or "release")
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "press" => Ok(KeyWait::Press),
+            "release" => Ok(KeyWait::Release),
+            _ => Err(format!(
+                "Unknown key wait mode '{}', expected press or release",
+                mode
+            )),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    pub addressing: Addressing,
+    // FX1E sets VF to 1 when I goes past 0xFFF, and to 0 otherwise (Amiga)
+    pub index_overflow: bool,
+    pub key_wait: KeyWait,
+}
+
+impl Default for Quirks {
+    fn default() -> Self {
+        Quirks {
+            addressing: Addressing::Wrap12,
+            index_overflow: false,
+            key_wait: KeyWait::Release,
+        }
+    }
+}
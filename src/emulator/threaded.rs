@@ -0,0 +1,471 @@
+use std::rc::Rc;
+
+use super::{decode, Chip8, Chip8Error, Chip8Result, Decoded, Op};
+use crate::keyboard::Keypad;
+use crate::{DISPLAY_LEN, RAM_SIZE, STACK_SIZE};
+
+// Threaded code backend. Code is compiled once into blocks of (handler,
+// operands) steps, following the program through jumps, calls, returns to
+// those calls and the not taken side of skips, so a loop usually compiles to
+// a single block that runs again in place. A taken skip, or a memory write
+// dropping compiled code, leaves the block early. Other returns, computed
+// jumps and key waits end blocks and run through the interpreter, as does
+// everything while instruction hooks are registered or the debugger is on.
+// Writes drop only the blocks containing the written byte, and code the
+// program overwrites after it was compiled is never compiled again.
+
+// Engine running the instructions
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Backend {
+    #[default]
+    Interpreter,
+    Threaded,
+}
+
+impl Backend {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "interpreter" => Ok(Backend::Interpreter),
+            "threaded" => Ok(Backend::Threaded),
+            _ => Err(format!(
+                "Unknown backend '{}', expected interpreter or threaded",
+                name
+            )),
+        }
+    }
+}
+
+// An instruction compiled to the function running it. Returns whether to
+// leave the block, to `Step::leave`
+type Thunk = fn(&mut Chip8, &Step, &mut dyn Keypad) -> bool;
+
+struct Step {
+    run: Thunk,
+    decoded: Decoded,
+    addr: u16,  // Address of the instruction
+    leave: u16, // Where to go on when the handler leaves the block
+}
+
+// How a block goes on after its last step
+#[derive(Clone, Copy)]
+enum Exit {
+    // Continue with the block at this address
+    Goto(usize),
+    // Interpret the instruction at this address, then continue from the pc
+    // it sets
+    Interpret(usize, Decoded),
+    // Interpret the instruction at the block start from memory, which was
+    // modified after it was compiled
+    Fallback,
+}
+
+struct Block {
+    steps: Box<[Step]>,
+    exit: Exit,
+    // Loops back to its start without changing anything but registers it
+    // sets to constants or the delay timer
+    spins: bool,
+}
+
+// Longest run of steps compiled into one block
+const MAX_STEPS: usize = 256;
+
+#[derive(Default)]
+pub(super) struct Blocks {
+    blocks: Vec<Option<Rc<Block>>>, // Compiled block at each start address
+    covering: Vec<Vec<u16>>,        // Start addresses of the blocks containing each byte
+    modified: Vec<bool>,            // Bytes written after they were compiled
+    drops: u64,                     // Times compiled blocks were dropped
+}
+
+impl Blocks {
+    // Drops compiled code containing the byte at `addr`, which the program
+    // overwrote. The byte is left to the interpreter from now on
+    pub(super) fn invalidate(&mut self, addr: usize) {
+        if self.forget(addr) {
+            self.modified[addr] = true;
+        }
+    }
+
+    // Drops the blocks containing the byte at `addr`, which a tool
+    // overwrote. Returns whether there were any
+    pub(super) fn forget(&mut self, addr: usize) -> bool {
+        match self.covering.get_mut(addr) {
+            Some(starts) if !starts.is_empty() => {
+                for start in starts.drain(..) {
+                    self.blocks[start as usize] = None;
+                }
+                self.drops += 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    // The block starting at `pc`, compiling it if needed
+    fn get(&mut self, pc: usize, ram: &[u8; RAM_SIZE]) -> Rc<Block> {
+        if self.blocks.is_empty() {
+            self.blocks = (0..RAM_SIZE).map(|_| None).collect();
+            self.covering = vec![Vec::new(); RAM_SIZE];
+            self.modified = vec![false; RAM_SIZE];
+        }
+        if let Some(block) = &self.blocks[pc] {
+            return Rc::clone(block);
+        }
+        let block = Rc::new(self.compile(pc, ram));
+        self.blocks[pc] = Some(Rc::clone(&block));
+        block
+    }
+
+    fn is_modified(&self, addr: usize) -> bool {
+        self.modified[addr] || self.modified[addr + 1]
+    }
+
+    // Compiles the code from `pc` on, until an instruction the block can't
+    // run, code it already contains or modified code
+    fn compile(&mut self, pc: usize, ram: &[u8; RAM_SIZE]) -> Block {
+        if self.is_modified(pc) {
+            return Block {
+                steps: Box::new([]),
+                exit: Exit::Fallback,
+                spins: false,
+            };
+        }
+
+        let mut steps: Vec<Step> = Vec::new();
+        // Return addresses of the calls in the block. Returning to one of
+        // them is compiled like a jump
+        let mut returns: Vec<usize> = Vec::new();
+        let mut addr = pc;
+        let exit = loop {
+            if addr + 1 >= RAM_SIZE
+                || self.is_modified(addr)
+                || steps.len() == MAX_STEPS
+                || steps.iter().any(|s| s.addr as usize == addr)
+            {
+                break Exit::Goto(addr);
+            }
+            let decoded = decode(((ram[addr] as u16) << 8) | ram[addr + 1] as u16);
+            let (run, next) = match (decoded.op, returns.last()) {
+                (Op::Ret, Some(_)) => (ret as Thunk, 0),
+                _ => match thunk(decoded.op) {
+                    Some(thunk) => thunk,
+                    None => break Exit::Interpret(addr, decoded),
+                },
+            };
+            steps.push(Step {
+                run,
+                decoded,
+                addr: addr as u16,
+                leave: (addr + 2 * next) as u16,
+            });
+            addr = match decoded.op {
+                Op::Jp => decoded.nnn as usize,
+                Op::Call => {
+                    returns.push(addr + 2);
+                    decoded.nnn as usize
+                }
+                Op::Ret => returns.pop().unwrap(),
+                _ => addr + 2,
+            };
+        };
+
+        let mut covered: Vec<usize> = steps.iter().map(|s| s.addr as usize).collect();
+        if let Exit::Interpret(addr, _) = exit {
+            covered.push(addr);
+        }
+        for addr in covered.into_iter().flat_map(|a| [a, a + 1]) {
+            if !self.covering[addr].contains(&(pc as u16)) {
+                self.covering[addr].push(pc as u16);
+            }
+        }
+        let spins = matches!(exit, Exit::Goto(addr) if addr == pc)
+            && steps.last().map(|s| s.decoded.op) == Some(Op::Jp)
+            && steps.iter().all(|s| spins(s.decoded.op));
+        Block {
+            steps: steps.into_boxed_slice(),
+            exit,
+            spins,
+        }
+    }
+}
+
+impl Chip8 {
+    // Runs a number of instructions block by block
+    pub(super) fn run_threaded(&mut self, count: u128, keypad: &mut dyn Keypad) -> Chip8Result<()> {
+        let mut remaining = count;
+        while remaining > 0 {
+            if self.has_instruction_hooks() {
+                return self.interpret_instructions(remaining, keypad);
+            }
+            let pc = self.pc;
+            if pc + 1 >= RAM_SIZE {
+                return Err(Chip8Error::PcOutOfBounds(pc));
+            }
+
+            let block = self.blocks.get(pc, &self.ram);
+            let drops = self.blocks.drops;
+            // Loops run the block again without looking it up
+            let mut laps = 0;
+            loop {
+                let ran = self.run_block(&block, remaining, keypad)?;
+                remaining -= ran;
+                if remaining == 0 || self.pc != pc || self.blocks.drops != drops {
+                    break;
+                }
+                // Keys and the delay timer don't change while a batch of
+                // instructions runs, so a spinning loop does the same from
+                // its second lap on. The laps left are only counted
+                let len = block.steps.len() as u128;
+                laps = if ran == len { laps + 1 } else { 0 };
+                if block.spins && laps == 2 {
+                    let skipped = remaining / len * len;
+                    self.instructions += skipped as u64;
+                    remaining -= skipped;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // Runs a block from its start, at most `budget` instructions, leaving
+    // `pc` at the next one. Returns the number of instructions run
+    fn run_block(
+        &mut self,
+        block: &Block,
+        budget: u128,
+        keypad: &mut dyn Keypad,
+    ) -> Chip8Result<u128> {
+        let count = (block.steps.len() as u128).min(budget) as usize;
+        for (i, step) in block.steps[..count].iter().enumerate() {
+            if (step.run)(self, step, keypad) {
+                self.pc = step.leave as usize;
+                self.instructions += i as u64 + 1;
+                if step.decoded.op == Op::Call {
+                    return Err(Chip8Error::StackOverflow(step.addr as usize));
+                }
+                return Ok(i as u128 + 1);
+            }
+        }
+        self.instructions += count as u64;
+        if count < block.steps.len() {
+            self.pc = block.steps[count].addr as usize;
+            return Ok(count as u128);
+        }
+
+        match block.exit {
+            Exit::Goto(addr) => self.pc = addr,
+            Exit::Interpret(addr, _) if budget == count as u128 => self.pc = addr,
+            Exit::Interpret(addr, decoded) => {
+                self.pc = addr + 2;
+                self.instructions += 1;
+                self.interpret(keypad, decoded)?;
+                return Ok(count as u128 + 1);
+            }
+            // Blocks of modified code have no steps, and the budget is never 0
+            Exit::Fallback => {
+                self.interpret_instructions(1, keypad)?;
+                return Ok(1);
+            }
+        }
+        Ok(count as u128)
+    }
+}
+
+// Handlers of the instructions blocks can contain, matching `interpret`,
+// and the number of instructions from a step to where it leaves the block
+fn thunk(op: Op) -> Option<(Thunk, usize)> {
+    let thunk: Thunk = match op {
+        Op::Cls => |c, _, _| {
+            c.display = [0; DISPLAY_LEN];
+            c.display_clear_flag = true;
+            false
+        },
+        // The block goes on at the target
+        Op::Jp => |_, _, _| false,
+        // A full stack leaves the block, which fails there
+        Op::Call => |c, s, _| {
+            if c.istack + 1 >= STACK_SIZE {
+                return true;
+            }
+            c.istack += 1;
+            c.stack[c.istack] = s.addr + 2;
+            false
+        },
+        Op::SeImm | Op::SneImm | Op::SeReg | Op::SneReg | Op::Skp | Op::Sknp => {
+            return Some((skip(op), 2))
+        }
+        Op::LdImm => |c, s, _| {
+            c.registers[s.decoded.x as usize] = s.decoded.nn;
+            false
+        },
+        Op::AddImm => |c, s, _| {
+            let x = s.decoded.x as usize;
+            c.registers[x] = c.registers[x].wrapping_add(s.decoded.nn);
+            false
+        },
+        Op::Ld => |c, s, _| {
+            c.registers[s.decoded.x as usize] = c.registers[s.decoded.y as usize];
+            false
+        },
+        Op::Or => |c, s, _| {
+            c.registers[s.decoded.x as usize] |= c.registers[s.decoded.y as usize];
+            false
+        },
+        Op::And => |c, s, _| {
+            c.registers[s.decoded.x as usize] &= c.registers[s.decoded.y as usize];
+            false
+        },
+        Op::Xor => |c, s, _| {
+            c.registers[s.decoded.x as usize] ^= c.registers[s.decoded.y as usize];
+            false
+        },
+        Op::Add => |c, s, _| {
+            let (x, y) = (s.decoded.x as usize, s.decoded.y as usize);
+            let (res, carry) = c.registers[x].overflowing_add(c.registers[y]);
+            c.registers[0x0F] = carry as u8;
+            c.registers[x] = res;
+            false
+        },
+        Op::Sub => |c, s, _| {
+            let x = s.decoded.x as usize;
+            let (vx, vy) = (c.registers[x], c.registers[s.decoded.y as usize]);
+            c.registers[0x0F] = (vx > vy) as u8;
+            c.registers[x] = vx.wrapping_sub(vy);
+            false
+        },
+        Op::Shr => |c, s, _| {
+            let x = s.decoded.x as usize;
+            c.registers[0x0F] = c.registers[x] & 0x01;
+            c.registers[x] >>= 1;
+            false
+        },
+        Op::Subn => |c, s, _| {
+            let x = s.decoded.x as usize;
+            let (vx, vy) = (c.registers[x], c.registers[s.decoded.y as usize]);
+            c.registers[0x0F] = (vy > vx) as u8;
+            c.registers[x] = vy.wrapping_sub(vx);
+            false
+        },
+        Op::Shl => |c, s, _| {
+            let x = s.decoded.x as usize;
+            c.registers[0x0F] = c.registers[x] & 0x80;
+            c.registers[x] = c.registers[x].wrapping_shl(1);
+            false
+        },
+        Op::LdI => |c, s, _| {
+            c.index = s.decoded.nnn;
+            false
+        },
+        Op::Rnd => |c, s, _| {
+            c.registers[s.decoded.x as usize] = s.decoded.nn & c.random_byte();
+            false
+        },
+        Op::Drw | Op::Bcd | Op::Store => return Some((write(op), 1)),
+        Op::LdVxDt => |c, s, _| {
+            c.registers[s.decoded.x as usize] = c.dt;
+            false
+        },
+        Op::LdDt => |c, s, _| {
+            c.dt = c.registers[s.decoded.x as usize];
+            false
+        },
+        Op::LdSt => |c, s, _| {
+            c.st = c.registers[s.decoded.x as usize];
+            false
+        },
+        Op::AddI => |c, s, _| {
+            let addr = c.index as u32 + c.registers[s.decoded.x as usize] as u32;
+            if c.quirks.index_overflow {
+                c.registers[0x0F] = (addr > 0x0FFF) as u8;
+            }
+            c.index = c.quirks.addressing.wrap(addr);
+            false
+        },
+        Op::LdFont => |c, s, _| {
+            c.index = c.registers[s.decoded.x as usize] as u16 * 0x05;
+            false
+        },
+        Op::Load => |c, s, _| {
+            for reg in 0..=s.decoded.x as usize {
+                c.registers[reg] = c.read_mem(c.index as usize + reg);
+            }
+            false
+        },
+        Op::Nop => |_, _, _| false,
+        // Returns, computed jumps and key waits end blocks
+        Op::Ret | Op::JpV0 | Op::LdKey => return None,
+    };
+    // Stack overflows fail past the call, like in the interpreter
+    let next = if op == Op::Call { 1 } else { 0 };
+    Some((thunk, next))
+}
+
+// Handler of a return to a call in the same block
+fn ret(c: &mut Chip8, _: &Step, _: &mut dyn Keypad) -> bool {
+    c.istack -= 1;
+    false
+}
+
+// Whether an instruction can be part of a spinning loop: it changes nothing,
+// or sets a register to the same value on every lap
+fn spins(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Jp
+            | Op::SeImm
+            | Op::SneImm
+            | Op::SeReg
+            | Op::SneReg
+            | Op::Skp
+            | Op::Sknp
+            | Op::LdImm
+            | Op::LdVxDt
+            | Op::Nop
+    )
+}
+
+// Handlers of the skips. Taking the skip leaves the block
+fn skip(op: Op) -> Thunk {
+    match op {
+        Op::SeImm => |c, s, _| c.registers[s.decoded.x as usize] == s.decoded.nn,
+        Op::SneImm => |c, s, _| c.registers[s.decoded.x as usize] != s.decoded.nn,
+        Op::SeReg => {
+            |c, s, _| c.registers[s.decoded.x as usize] == c.registers[s.decoded.y as usize]
+        }
+        Op::SneReg => {
+            |c, s, _| c.registers[s.decoded.x as usize] != c.registers[s.decoded.y as usize]
+        }
+        Op::Skp => |c, s, k| k.is_pressed(c.registers[s.decoded.x as usize] & 0x0F),
+        _ => |c, s, k| !k.is_pressed(c.registers[s.decoded.x as usize] & 0x0F),
+    }
+}
+
+// Handlers of the draws and memory writes. Hooks they run see the pc past
+// the instruction, and dropping compiled code leaves the block
+fn write(op: Op) -> Thunk {
+    match op {
+        Op::Drw => |c, s, _| {
+            let drops = c.blocks.drops;
+            c.pc = s.addr as usize + 2;
+            c.draw(
+                s.decoded.x as usize,
+                s.decoded.y as usize,
+                s.decoded.n as usize,
+            );
+            c.blocks.drops != drops
+        },
+        Op::Bcd => |c, s, _| {
+            let drops = c.blocks.drops;
+            c.pc = s.addr as usize + 2;
+            c.store_bcd(s.decoded.x as usize);
+            c.blocks.drops != drops
+        },
+        _ => |c, s, _| {
+            let drops = c.blocks.drops;
+            c.pc = s.addr as usize + 2;
+            c.store_registers(s.decoded.x as usize);
+            c.blocks.drops != drops
+        },
+    }
+}
@@ -0,0 +1,398 @@
+use std::{convert::TryFrom, fs};
+
+use serde_json::Value;
+
+use crate::{
+    emulator::{Chip8, Chip8Result, Quirks},
+    keyboard::MaskKeypad,
+    util::crc32,
+    DEF_IPS, DISPLAY_LEN, FONTS, RAM_SIZE,
+};
+
+// Reinforcement learning environment. An agent picks a set of keys, the
+// machine runs for a few frames with them held, and the agent gets the
+// new framebuffer, a reward and whether the episode is over.
+//
+// What counts as reward and game over is game specific, so each ROM comes
+// with a JSON descriptor reading values from RAM or registers:
+//
+//   {
+//     "rom_hash": "0x8f3a9b12",
+//     "frames_per_step": 4,
+//     "max_frames": 18000,
+//     "reward": [{"value": {"bcd": "0x2f0", "digits": 3}, "scale": 1}],
+//     "done": [{"value": {"reg": 14}, "equals": 0}]
+//   }
+//
+// The reward is the sum of the changes of the reward values since the last
+// step, times their scale. The episode is done once any done condition
+// holds ("equals", "at_least" or "at_most"), or after `max_frames` frames.
+//
+// Numbers other than the scale and the conditions are unsigned integers,
+// written as JSON numbers or as strings. Strings are decimal ("752") or hex
+// with a 0x prefix ("0x2f0").
+
+// Default number of frames an action is held for
+pub const DEF_FRAMES_PER_STEP: u32 = 4;
+// Most BCD digits a value can have, so it fits in an i64
+pub const MAX_BCD_DIGITS: usize = 18;
+
+// Where a game value is read from
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Source {
+    // A V register
+    Register(usize),
+    // A byte of RAM
+    Ram(usize),
+    // Decimal digits stored one per byte, most significant first, as
+    // written by FX33
+    Bcd(usize, usize),
+}
+
+impl Source {
+    fn parse(value: &Value) -> Result<Self, String> {
+        if let Some(reg) = value.get("reg") {
+            let reg = number(reg, "reg")?;
+            if reg >= 16 {
+                return Err(format!("Register {} does not exist", reg));
+            }
+            return Ok(Source::Register(reg as usize));
+        }
+        if let Some(addr) = value.get("ram") {
+            return Ok(Source::Ram(address(addr, 1)?));
+        }
+        if let Some(addr) = value.get("bcd") {
+            let digits = match value.get("digits") {
+                Some(digits) => number(digits, "digits")? as usize,
+                None => 3,
+            };
+            if digits == 0 || digits > MAX_BCD_DIGITS {
+                return Err(format!(
+                    "\"digits\" must be from 1 to {}, got {}",
+                    MAX_BCD_DIGITS, digits
+                ));
+            }
+            return Ok(Source::Bcd(address(addr, digits)?, digits));
+        }
+        Err(format!("Expected \"reg\", \"ram\" or \"bcd\" in {}", value))
+    }
+
+    pub fn read(self, chip8: &Chip8) -> i64 {
+        match self {
+            Source::Register(reg) => chip8.registers[reg] as i64,
+            Source::Ram(addr) => chip8.ram[addr] as i64,
+            Source::Bcd(addr, digits) => chip8.ram[addr..addr + digits]
+                .iter()
+                .fold(0i64, |n, &d| n.saturating_mul(10).saturating_add(d as i64)),
+        }
+    }
+}
+
+// Unsigned integer, as a number or a decimal or 0x prefixed hex string
+fn number(value: &Value, name: &str) -> Result<u64, String> {
+    match value {
+        Value::Number(n) => n.as_u64(),
+        Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u64::from_str_radix(hex, 16).ok(),
+            None => s.parse::<u64>().ok(),
+        },
+        _ => None,
+    }
+    .ok_or_else(|| format!("\"{}\" must be an unsigned integer or hex string", name))
+}
+
+fn number_u32(value: &Value, name: &str) -> Result<u32, String> {
+    u32::try_from(number(value, name)?)
+        .map_err(|_| format!("\"{}\" must be at most {}", name, u32::MAX))
+}
+
+fn address(value: &Value, len: usize) -> Result<usize, String> {
+    let addr = number(value, "address")? as usize;
+    if addr >= RAM_SIZE || len > RAM_SIZE - addr {
+        return Err(format!("Address {:#x} is outside of memory", addr));
+    }
+    Ok(addr)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Reward {
+    pub source: Source,
+    pub scale: f64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Condition {
+    Equals(i64),
+    AtLeast(i64),
+    AtMost(i64),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Done {
+    pub source: Source,
+    pub condition: Condition,
+}
+
+impl Done {
+    fn holds(&self, chip8: &Chip8) -> bool {
+        let value = self.source.read(chip8);
+        match self.condition {
+            Condition::Equals(n) => value == n,
+            Condition::AtLeast(n) => value >= n,
+            Condition::AtMost(n) => value <= n,
+        }
+    }
+}
+
+// Game specific settings of an environment
+#[derive(Clone, Debug, PartialEq)]
+pub struct Descriptor {
+    pub rom_hash: Option<u32>,
+    pub frames_per_step: u32,
+    pub max_frames: Option<u64>,
+    pub reward: Vec<Reward>,
+    pub done: Vec<Done>,
+}
+
+impl Descriptor {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read descriptor {}: {}", path, e))?;
+        Descriptor::parse(&text).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let json: Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let rom_hash = match json.get("rom_hash") {
+            Some(hash) => Some(number_u32(hash, "rom_hash")?),
+            None => None,
+        };
+        let frames_per_step = match json.get("frames_per_step") {
+            Some(n) => number_u32(n, "frames_per_step")?.max(1),
+            None => DEF_FRAMES_PER_STEP,
+        };
+        let max_frames = match json.get("max_frames") {
+            Some(n) => Some(number(n, "max_frames")?),
+            None => None,
+        };
+
+        let mut reward = Vec::new();
+        for r in list(&json, "reward")? {
+            reward.push(Reward {
+                source: Source::parse(r.get("value").ok_or("Reward without \"value\"")?)?,
+                scale: match r.get("scale") {
+                    Some(s) => s.as_f64().ok_or("\"scale\" must be a number")?,
+                    None => 1.0,
+                },
+            });
+        }
+
+        let mut done = Vec::new();
+        for d in list(&json, "done")? {
+            let source = Source::parse(d.get("value").ok_or("Done without \"value\"")?)?;
+            let int = |name: &str| {
+                d.get(name)
+                    .map(|v| v.as_i64().ok_or(format!("\"{}\" must be an integer", name)))
+            };
+            let condition = match (int("equals"), int("at_least"), int("at_most")) {
+                (Some(n), _, _) => Condition::Equals(n?),
+                (_, Some(n), _) => Condition::AtLeast(n?),
+                (_, _, Some(n)) => Condition::AtMost(n?),
+                _ => return Err("Done without \"equals\", \"at_least\" or \"at_most\"".into()),
+            };
+            done.push(Done { source, condition });
+        }
+
+        Ok(Descriptor {
+            rom_hash,
+            frames_per_step,
+            max_frames,
+            reward,
+            done,
+        })
+    }
+}
+
+fn list<'a>(json: &'a Value, name: &str) -> Result<&'a [Value], String> {
+    match json.get(name) {
+        Some(Value::Array(items)) => Ok(items),
+        Some(_) => Err(format!("\"{}\" must be a list", name)),
+        None => Ok(&[]),
+    }
+}
+
+// Result of a step
+#[derive(Clone, Debug, PartialEq)]
+pub struct Step {
+    pub observation: Vec<u8>,
+    pub reward: f64,
+    pub done: bool,
+}
+
+pub struct Env {
+    pub descriptor: Descriptor,
+    // Seed of the next episode, incremented on each reset so episodes
+    // differ but runs are reproducible
+    pub seed: u64,
+    pub quirks: Quirks,
+    pub instruction_time_ns: u128,
+    pub chip8: Chip8,
+    rom: Vec<u8>,
+    frame: u64,              // Frames run this episode
+    reward_values: Vec<i64>, // Reward values after the last step
+}
+
+impl Env {
+    pub fn new(rom: Vec<u8>, descriptor: Descriptor) -> Result<Self, String> {
+        let hash = crc32(&rom);
+        if let Some(expected) = descriptor.rom_hash {
+            if hash != expected {
+                return Err(format!(
+                    "The descriptor is for ROM {:08x}, this ROM is {:08x}",
+                    expected, hash
+                ));
+            }
+        }
+        let instruction_time_ns = 1e9 as u128 / DEF_IPS as u128;
+        let chip8 = Chip8::new(rom.clone(), FONTS, 0, instruction_time_ns, false);
+        let mut env = Env {
+            descriptor,
+            seed: 0,
+            quirks: Quirks::default(),
+            instruction_time_ns,
+            chip8,
+            rom,
+            frame: 0,
+            reward_values: Vec::new(),
+        };
+        env.reset();
+        Ok(env)
+    }
+
+    // Starts a new episode, returning the first observation
+    pub fn reset(&mut self) -> Vec<u8> {
+        let mut chip8 = Chip8::new(self.rom.clone(), FONTS, 0, self.instruction_time_ns, false);
+        chip8.quirks = self.quirks;
+        chip8.seed(self.seed);
+        self.seed = self.seed.wrapping_add(1);
+        self.chip8 = chip8;
+        self.frame = 0;
+        self.reward_values = self.read_rewards();
+        self.observation()
+    }
+
+    // Holds the keys in `action` (bit N for key N) for the descriptor's
+    // frames per step. Emulator errors end the episode
+    pub fn step(&mut self, action: u16) -> Chip8Result<Step> {
+        let mut keypad = MaskKeypad(action);
+        for _ in 0..self.descriptor.frames_per_step {
+            self.chip8.run_frame(&mut keypad)?;
+            self.frame += 1;
+        }
+
+        let values = self.read_rewards();
+        let reward = self
+            .descriptor
+            .reward
+            .iter()
+            .zip(values.iter().zip(self.reward_values.iter()))
+            .map(|(r, (new, old))| (new - old) as f64 * r.scale)
+            .sum();
+        self.reward_values = values;
+
+        let done = self.descriptor.done.iter().any(|d| d.holds(&self.chip8))
+            || self
+                .descriptor
+                .max_frames
+                .is_some_and(|max| self.frame >= max);
+
+        Ok(Step {
+            observation: self.observation(),
+            reward,
+            done,
+        })
+    }
+
+    // The framebuffer as a bit array, row-major with 8 pixels per byte,
+    // most significant bit first
+    pub fn observation(&self) -> Vec<u8> {
+        let mut bits = vec![0; DISPLAY_LEN / 8];
+        for (i, &pixel) in self.chip8.display.iter().enumerate() {
+            if pixel > 0 {
+                bits[i / 8] |= 0x80 >> (i % 8);
+            }
+        }
+        bits
+    }
+
+    fn read_rewards(&self) -> Vec<i64> {
+        self.descriptor
+            .reward
+            .iter()
+            .map(|r| r.source.read(&self.chip8))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn rewards_held_keys() {
+        // Score in V1 counts frames key 5 is held, the game ends at 3
+        let rom = vec![
+            0x60, 0x05, // V0 = 5
+            0xE0, 0xA1, // SKNP V0
+            0x71, 0x01, // V1 += 1
+            0xF2, 0x07, // V2 = DT
+            0x32, 0x00, // SE V2, 0
+            0x12, 0x06, // JP 206
+            0x62, 0x01, // V2 = 1
+            0xF2, 0x15, // DT = V2
+            0x12, 0x02, // JP 202
+        ];
+        let descriptor = Descriptor::parse(
+            r#"{
+                "frames_per_step": 1,
+                "reward": [{"value": {"reg": 1}, "scale": 0.5}],
+                "done": [{"value": {"reg": "1"}, "at_least": 3}]
+            }"#,
+        )
+        .unwrap();
+        let mut env = Env::new(rom, descriptor).unwrap();
+
+        assert_eq!(env.step(0).unwrap().reward, 0.0);
+        let step = env.step(1 << 5).unwrap();
+        assert_eq!((step.reward, step.done), (0.5, false));
+        env.step(1 << 5).unwrap();
+        assert!(env.step(1 << 5).unwrap().done);
+
+        let observation = env.reset();
+        assert_eq!(observation.len(), DISPLAY_LEN / 8);
+        assert_eq!(env.chip8.registers[1], 0);
+    }
+
+    #[test]
+    fn parses_sources() {
+        let source = |json: &str| Source::parse(&serde_json::from_str(json).unwrap());
+        assert_eq!(source(r#"{"ram": "10"}"#), Ok(Source::Ram(10)));
+        assert_eq!(source(r#"{"ram": "0x10"}"#), Ok(Source::Ram(0x10)));
+        assert_eq!(source(r#"{"bcd": 4093}"#), Ok(Source::Bcd(4093, 3)));
+        assert!(source(r#"{"bcd": 4094}"#).is_err());
+        assert!(source(r#"{"ram": "ff"}"#).is_err());
+        assert!(source(r#"{"bcd": 0, "digits": 18446744073709551615}"#).is_err());
+        assert!(source(r#"{"bcd": 0, "digits": 19}"#).is_err());
+
+        let descriptor = Descriptor::parse(r#"{"rom_hash": "0x8f3a9b12", "frames_per_step": "4"}"#);
+        assert_eq!(descriptor.unwrap().rom_hash, Some(0x8f3a9b12));
+        assert!(Descriptor::parse(r#"{"rom_hash": "8f3a9b12"}"#).is_err());
+        assert!(Descriptor::parse(r#"{"frames_per_step": 4294967296}"#).is_err());
+
+        let chip8 = Chip8::new(Vec::new(), [0xFF; 80], 0, 1, false);
+        assert_eq!(
+            source(r#"{"bcd": 0, "digits": 18}"#).unwrap().read(&chip8),
+            i64::MAX
+        );
+    }
+}
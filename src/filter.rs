@@ -0,0 +1,371 @@
+// CPU-side post-processing applied to the colored framebuffer before it is
+// uploaded to the display texture. Frames are RGB24, row-major.
+
+// Bytes per pixel of a frame (RGB24)
+const BYTES_PER_PIXEL: usize = 3;
+
+// Brightness kept on darkened scanline rows
+const SCANLINE_LEVEL: f32 = 0.55;
+// Brightness kept on pixel grid lines
+const GRID_LEVEL: f32 = 0.7;
+// Strength of the blurred glow added back by the bloom filter
+const BLOOM_STRENGTH: f32 = 0.6;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    pub data: Vec<u8>,
+}
+
+impl Frame {
+    pub fn new(width: usize, height: usize, data: Vec<u8>) -> Self {
+        debug_assert_eq!(data.len(), width * height * BYTES_PER_PIXEL);
+        Frame {
+            width,
+            height,
+            data,
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let i = (y * self.width + x) * BYTES_PER_PIXEL;
+        [self.data[i], self.data[i + 1], self.data[i + 2]]
+    }
+
+    // Pixel at the given offset from (x, y), clamped to the frame edges
+    fn neighbor(&self, x: usize, y: usize, dx: isize, dy: isize) -> [u8; 3] {
+        let nx = (x as isize + dx).max(0).min(self.width as isize - 1) as usize;
+        let ny = (y as isize + dy).max(0).min(self.height as isize - 1) as usize;
+        self.pixel(nx, ny)
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, col: [u8; 3]) {
+        let i = (y * self.width + x) * BYTES_PER_PIXEL;
+        self.data[i..i + BYTES_PER_PIXEL].copy_from_slice(&col);
+    }
+
+    fn darken_pixel(&mut self, x: usize, y: usize, level: f32) {
+        let i = (y * self.width + x) * BYTES_PER_PIXEL;
+        for c in &mut self.data[i..i + BYTES_PER_PIXEL] {
+            *c = (*c as f32 * level) as u8;
+        }
+    }
+}
+
+// Smoothing upscaler applied before the other filters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Upscale {
+    None,
+    Scale2x,
+    Scale3x,
+}
+
+impl Upscale {
+    pub fn factor(self) -> usize {
+        match self {
+            Upscale::None => 1,
+            Upscale::Scale2x => 2,
+            Upscale::Scale3x => 3,
+        }
+    }
+
+    // Cycles None -> Scale2x -> Scale3x -> None
+    pub fn next(self) -> Self {
+        match self {
+            Upscale::None => Upscale::Scale2x,
+            Upscale::Scale2x => Upscale::Scale3x,
+            Upscale::Scale3x => Upscale::None,
+        }
+    }
+}
+
+// Filter pipeline configuration
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Filters {
+    pub upscale: Upscale,
+    pub scanlines: bool,
+    pub grid: bool,
+    pub bloom: bool,
+}
+
+impl Default for Filters {
+    fn default() -> Self {
+        Filters {
+            upscale: Upscale::None,
+            scanlines: false,
+            grid: false,
+            bloom: false,
+        }
+    }
+}
+
+impl Filters {
+    // Parses a list of filter names, e.g. ["scanlines", "scale2x"]
+    pub fn parse<'a, I: IntoIterator<Item = &'a str>>(names: I) -> Result<Self, String> {
+        let mut filters = Filters::default();
+        for name in names {
+            match name {
+                "scanlines" => filters.scanlines = true,
+                "grid" => filters.grid = true,
+                "scale2x" => filters.upscale = Upscale::Scale2x,
+                "scale3x" => filters.upscale = Upscale::Scale3x,
+                "bloom" => filters.bloom = true,
+                _ => return Err(format!("Unknown filter '{}'", name)),
+            }
+        }
+        Ok(filters)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        *self == Filters::default()
+    }
+
+    // Runs the pipeline on a native resolution frame. `scale` is the display
+    // scale factor, used to size the cells scanlines and the grid are drawn on
+    pub fn apply(&self, frame: Frame, scale: u32) -> Frame {
+        if self.is_empty() {
+            return frame;
+        }
+
+        let upscale = self.upscale.factor();
+        let mut frame = match self.upscale {
+            Upscale::None => frame,
+            Upscale::Scale2x => scale2x(&frame),
+            Upscale::Scale3x => scale3x(&frame),
+        };
+
+        if self.scanlines || self.grid || self.bloom {
+            // Blow each source pixel up to a cell of about `scale` pixels
+            let factor = (scale as usize / upscale).max(1);
+            frame = scale_nearest(&frame, factor);
+            let cell = upscale * factor;
+            if self.scanlines {
+                scanlines(&mut frame, cell);
+            }
+            if self.grid {
+                grid(&mut frame, cell);
+            }
+            if self.bloom {
+                bloom(&mut frame, (cell / 2).max(1));
+            }
+        }
+        frame
+    }
+}
+
+// Nearest neighbor integer upscaling
+pub fn scale_nearest(frame: &Frame, factor: usize) -> Frame {
+    if factor == 1 {
+        return frame.clone();
+    }
+    let width = frame.width * factor;
+    let height = frame.height * factor;
+    let row_len = width * BYTES_PER_PIXEL;
+    let mut data = Vec::with_capacity(row_len * height);
+    for y in 0..frame.height {
+        let row_start = data.len();
+        for x in 0..frame.width {
+            let col = frame.pixel(x, y);
+            for _ in 0..factor {
+                data.extend_from_slice(&col);
+            }
+        }
+        for _ in 1..factor {
+            data.extend_from_within(row_start..row_start + row_len);
+        }
+    }
+    Frame::new(width, height, data)
+}
+
+// Scale2x (EPX) smoothing, doubles the resolution
+pub fn scale2x(frame: &Frame) -> Frame {
+    let mut out = Frame::new(
+        frame.width * 2,
+        frame.height * 2,
+        vec![0; frame.data.len() * 4],
+    );
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            let p = frame.pixel(x, y);
+            let a = frame.neighbor(x, y, 0, -1);
+            let b = frame.neighbor(x, y, 1, 0);
+            let c = frame.neighbor(x, y, -1, 0);
+            let d = frame.neighbor(x, y, 0, 1);
+
+            let e0 = if c == a && c != d && a != b { a } else { p };
+            let e1 = if a == b && a != c && b != d { b } else { p };
+            let e2 = if d == c && d != b && c != a { c } else { p };
+            let e3 = if b == d && b != a && d != c { d } else { p };
+
+            out.set_pixel(x * 2, y * 2, e0);
+            out.set_pixel(x * 2 + 1, y * 2, e1);
+            out.set_pixel(x * 2, y * 2 + 1, e2);
+            out.set_pixel(x * 2 + 1, y * 2 + 1, e3);
+        }
+    }
+    out
+}
+
+// Scale3x smoothing, triples the resolution
+pub fn scale3x(frame: &Frame) -> Frame {
+    let mut out = Frame::new(
+        frame.width * 3,
+        frame.height * 3,
+        vec![0; frame.data.len() * 9],
+    );
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            // A B C
+            // D E F
+            // G H I
+            let a = frame.neighbor(x, y, -1, -1);
+            let b = frame.neighbor(x, y, 0, -1);
+            let c = frame.neighbor(x, y, 1, -1);
+            let d = frame.neighbor(x, y, -1, 0);
+            let e = frame.pixel(x, y);
+            let f = frame.neighbor(x, y, 1, 0);
+            let g = frame.neighbor(x, y, -1, 1);
+            let h = frame.neighbor(x, y, 0, 1);
+            let i = frame.neighbor(x, y, 1, 1);
+
+            let db = d == b && b != f && d != h;
+            let bf = b == f && b != d && f != h;
+            let dh = d == h && d != b && h != f;
+            let hf = h == f && d != h && b != f;
+
+            let cells = [
+                if db { d } else { e },
+                if (db && e != c) || (bf && e != a) {
+                    b
+                } else {
+                    e
+                },
+                if bf { f } else { e },
+                if (db && e != g) || (dh && e != a) {
+                    d
+                } else {
+                    e
+                },
+                e,
+                if (bf && e != i) || (hf && e != c) {
+                    f
+                } else {
+                    e
+                },
+                if dh { d } else { e },
+                if (dh && e != i) || (hf && e != g) {
+                    h
+                } else {
+                    e
+                },
+                if hf { f } else { e },
+            ];
+            for (n, col) in cells.iter().enumerate() {
+                out.set_pixel(x * 3 + n % 3, y * 3 + n / 3, *col);
+            }
+        }
+    }
+    out
+}
+
+// Darkens the lower rows of each cell, like the gaps between CRT scanlines
+pub fn scanlines(frame: &mut Frame, cell: usize) {
+    if cell < 2 {
+        return;
+    }
+    let dark_rows = (cell / 3).max(1);
+    for y in 0..frame.height {
+        if y % cell >= cell - dark_rows {
+            for x in 0..frame.width {
+                frame.darken_pixel(x, y, SCANLINE_LEVEL);
+            }
+        }
+    }
+}
+
+// Darkens the last row and column of each cell to outline pixels
+pub fn grid(frame: &mut Frame, cell: usize) {
+    if cell < 2 {
+        return;
+    }
+    for y in 0..frame.height {
+        for x in 0..frame.width {
+            if x % cell == cell - 1 || y % cell == cell - 1 {
+                frame.darken_pixel(x, y, GRID_LEVEL);
+            }
+        }
+    }
+}
+
+// Soft CRT glow: adds a box-blurred copy of the frame back onto itself
+pub fn bloom(frame: &mut Frame, radius: usize) {
+    let (w, h) = (frame.width, frame.height);
+    let horizontal = box_blur(&frame.data, w, h, radius, 1, w);
+    let blurred = box_blur(&horizontal, h, w, radius, w, 1);
+    for (c, glow) in frame.data.iter_mut().zip(blurred.iter()) {
+        *c = (*c as f32 + *glow as f32 * BLOOM_STRENGTH).min(255.0) as u8;
+    }
+}
+
+// One pass of a separable box blur. Walks `lines` lines of `len` pixels,
+// where `step` is the pixel stride along a line and `stride` the stride
+// between lines
+fn box_blur(
+    data: &[u8],
+    len: usize,
+    lines: usize,
+    radius: usize,
+    step: usize,
+    stride: usize,
+) -> Vec<u8> {
+    let mut out = vec![0; data.len()];
+    let window = (radius * 2 + 1) as u32;
+    for line in 0..lines {
+        for ch in 0..BYTES_PER_PIXEL {
+            let at = |i: usize| (line * stride + i * step) * BYTES_PER_PIXEL + ch;
+            // Running sum over the window, edges are clamped
+            let mut sum: u32 = 0;
+            for i in 0..=radius * 2 {
+                sum += data[at(i.saturating_sub(radius).min(len - 1))] as u32;
+            }
+            for i in 0..len {
+                out[at(i)] = (sum / window) as u8;
+                let leaving = i.saturating_sub(radius);
+                let entering = (i + radius + 1).min(len - 1);
+                sum = sum + data[at(entering)] as u32 - data[at(leaving)] as u32;
+            }
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ON: [u8; 3] = [255, 255, 255];
+    const OFF: [u8; 3] = [0, 0, 0];
+
+    fn frame(width: usize, height: usize, pixels: &[[u8; 3]]) -> Frame {
+        Frame::new(width, height, pixels.concat())
+    }
+
+    #[test]
+    fn scale2x_rounds_diagonals() {
+        // A diagonal line gets its corners filled in
+        let src = frame(2, 2, &[ON, OFF, OFF, ON]);
+        let out = scale2x(&src);
+        assert_eq!((out.width, out.height), (4, 4));
+        assert_eq!(out.pixel(2, 1), ON);
+        assert_eq!(out.pixel(1, 2), ON);
+        assert_eq!(out.pixel(3, 0), OFF);
+    }
+
+    #[test]
+    fn scale3x_keeps_flat_areas() {
+        let src = frame(2, 1, &[ON, ON]);
+        let out = scale3x(&src);
+        assert_eq!(out, scale_nearest(&src, 3));
+    }
+}
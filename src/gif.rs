@@ -0,0 +1,270 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+// Largest LZW code allowed by GIF (12 bits)
+const MAX_CODE: u16 = 4095;
+// Minimum LZW code size, GIF does not allow less than 2 even for 2 colors
+const MIN_CODE_SIZE: u8 = 2;
+
+// Records frames into an animated GIF with a two color palette. Identical
+// consecutive frames are merged into one frame with a longer delay
+pub struct GifRecorder {
+    out: BufWriter<File>,
+    width: usize,
+    height: usize,
+    scale: usize,
+    last_frame: Option<Vec<u8>>, // Last captured frame, not yet written
+    ticks: u64,                  // Ticks captured so far
+    written_cs: u64,             // Playback time written so far (1/100 s)
+    last_start_tick: u64,        // Tick the pending frame was first seen at
+    tick_rate: u64,              // Captures per second
+}
+
+impl GifRecorder {
+    // Creates the file and writes the GIF header. The framebuffer is
+    // `width` x `height`, and each pixel is scaled up to `scale` x `scale`
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        scale: u32,
+        tick_rate: u64,
+        fg_col: (u8, u8, u8),
+        bg_col: (u8, u8, u8),
+    ) -> io::Result<Self> {
+        let scale = scale.max(1) as usize;
+        let mut out = BufWriter::new(File::create(path)?);
+
+        // Header and logical screen descriptor with a 2 entry global palette
+        out.write_all(b"GIF89a")?;
+        out.write_all(&((width * scale) as u16).to_le_bytes())?;
+        out.write_all(&((height * scale) as u16).to_le_bytes())?;
+        out.write_all(&[0x80, 0, 0])?;
+        for &(r, g, b) in &[bg_col, fg_col] {
+            out.write_all(&[r, g, b])?;
+        }
+
+        // NETSCAPE2.0 extension, loop forever
+        out.write_all(&[0x21, 0xFF, 0x0B])?;
+        out.write_all(b"NETSCAPE2.0")?;
+        out.write_all(&[0x03, 0x01, 0x00, 0x00, 0x00])?;
+
+        Ok(GifRecorder {
+            out,
+            width,
+            height,
+            scale,
+            last_frame: None,
+            ticks: 0,
+            written_cs: 0,
+            last_start_tick: 0,
+            tick_rate,
+        })
+    }
+
+    // Captures one frame of the framebuffer (one byte per pixel)
+    pub fn capture(&mut self, buffer: &[u8]) -> io::Result<()> {
+        if self.last_frame.as_deref() != Some(buffer) {
+            self.flush_frame()?;
+            self.last_frame = Some(buffer.to_vec());
+            self.last_start_tick = self.ticks;
+        }
+        self.ticks += 1;
+        Ok(())
+    }
+
+    // Writes the pending frame and the trailer
+    pub fn finish(mut self) -> io::Result<()> {
+        self.flush_frame()?;
+        self.out.write_all(&[0x3B])?;
+        self.out.flush()
+    }
+
+    // Writes the pending frame, shown until the current tick
+    fn flush_frame(&mut self) -> io::Result<()> {
+        let frame = match self.last_frame.take() {
+            Some(frame) => frame,
+            None => return Ok(()),
+        };
+        // Delays are rounded against the total elapsed time so they don't drift
+        let end_cs = self.ticks * 100 / self.tick_rate;
+        let delay = (end_cs - self.written_cs).max(1).min(u16::MAX as u64);
+        self.written_cs += delay;
+
+        // Graphic control extension with the frame delay
+        self.out.write_all(&[0x21, 0xF9, 0x04, 0x00])?;
+        self.out.write_all(&(delay as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00, 0x00])?;
+
+        // Image descriptor covering the whole screen, no local palette
+        let (w, h) = (self.width * self.scale, self.height * self.scale);
+        self.out.write_all(&[0x2C, 0, 0, 0, 0])?;
+        self.out.write_all(&(w as u16).to_le_bytes())?;
+        self.out.write_all(&(h as u16).to_le_bytes())?;
+        self.out.write_all(&[0x00])?;
+
+        let mut indices = Vec::with_capacity(w * h);
+        for row in frame.chunks(self.width).take(self.height) {
+            let start = indices.len();
+            for &p in row {
+                indices.extend(std::iter::repeat_n((p > 0) as u8, self.scale));
+            }
+            for _ in 1..self.scale {
+                indices.extend_from_within(start..start + w);
+            }
+        }
+        self.out.write_all(&[MIN_CODE_SIZE])?;
+        for block in lzw_encode(&indices, MIN_CODE_SIZE).chunks(255) {
+            self.out.write_all(&[block.len() as u8])?;
+            self.out.write_all(block)?;
+        }
+        self.out.write_all(&[0x00])
+    }
+}
+
+// Packs variable width codes LSB first
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u32,
+    bits: u8,
+}
+
+impl BitWriter {
+    fn write(&mut self, code: u16, size: u8) {
+        self.acc |= (code as u32) << self.bits;
+        self.bits += size;
+        while self.bits >= 8 {
+            self.bytes.push(self.acc as u8);
+            self.acc >>= 8;
+            self.bits -= 8;
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.bits > 0 {
+            self.bytes.push(self.acc as u8);
+        }
+        self.bytes
+    }
+}
+
+// GIF flavoured LZW compression of color indices
+pub fn lzw_encode(indices: &[u8], min_code_size: u8) -> Vec<u8> {
+    let clear = 1_u16 << min_code_size;
+    let end = clear + 1;
+    let mut writer = BitWriter {
+        bytes: Vec::new(),
+        acc: 0,
+        bits: 0,
+    };
+    let mut dict: HashMap<(u16, u8), u16> = HashMap::new();
+    let mut next_code = end + 1;
+    let mut code_size = min_code_size + 1;
+
+    writer.write(clear, code_size);
+    let mut iter = indices.iter();
+    let mut prefix = match iter.next() {
+        Some(&first) => first as u16,
+        None => {
+            writer.write(end, code_size);
+            return writer.finish();
+        }
+    };
+    for &k in iter {
+        if let Some(&code) = dict.get(&(prefix, k)) {
+            prefix = code;
+            continue;
+        }
+        writer.write(prefix, code_size);
+        if next_code > MAX_CODE {
+            // Table full, start over
+            writer.write(clear, code_size);
+            dict.clear();
+            next_code = end + 1;
+            code_size = min_code_size + 1;
+        } else {
+            dict.insert((prefix, k), next_code);
+            if next_code == 1 << code_size && code_size < 12 {
+                code_size += 1;
+            }
+            next_code += 1;
+        }
+        prefix = k as u16;
+    }
+    writer.write(prefix, code_size);
+    writer.write(end, code_size);
+    writer.finish()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // Reference GIF LZW decoder
+    fn lzw_decode(data: &[u8], min_code_size: u8) -> Vec<u8> {
+        let clear = 1_usize << min_code_size;
+        let end = clear + 1;
+        let reset = || -> Vec<Vec<u8>> { (0..clear + 2).map(|i| vec![i as u8]).collect() };
+        let mut table = reset();
+        let mut code_size = min_code_size + 1;
+        let (mut acc, mut bits, mut pos) = (0_u32, 0_u8, 0);
+        let mut prev: Option<usize> = None;
+        let mut out = Vec::new();
+        loop {
+            while bits < code_size {
+                acc |= (data[pos] as u32) << bits;
+                pos += 1;
+                bits += 8;
+            }
+            let code = (acc & ((1 << code_size) - 1)) as usize;
+            acc >>= code_size;
+            bits -= code_size;
+            if code == clear {
+                table = reset();
+                code_size = min_code_size + 1;
+                prev = None;
+                continue;
+            }
+            if code == end {
+                return out;
+            }
+            let entry = match (table.get(code), prev) {
+                (Some(e), _) => e.clone(),
+                (None, Some(p)) => {
+                    let mut e = table[p].clone();
+                    e.push(table[p][0]);
+                    e
+                }
+                _ => panic!("invalid code"),
+            };
+            if let Some(p) = prev {
+                let mut e = table[p].clone();
+                e.push(entry[0]);
+                table.push(e);
+                if table.len() == 1 << code_size && code_size < 12 {
+                    code_size += 1;
+                }
+            }
+            out.extend_from_slice(&entry);
+            prev = Some(code);
+        }
+    }
+
+    #[test]
+    fn lzw_round_trip() {
+        // Long enough to fill the code table and force a clear
+        let mut seed = 1_u32;
+        let indices: Vec<u8> = (0..100_000)
+            .map(|_| {
+                seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+                (seed >> 16) as u8 & 1
+            })
+            .collect();
+        assert_eq!(lzw_decode(&lzw_encode(&indices, 2), 2), indices);
+        assert_eq!(lzw_decode(&lzw_encode(&[], 2), 2), Vec::<u8>::new());
+    }
+}
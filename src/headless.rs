@@ -0,0 +1,186 @@
+use std::fmt::Write;
+
+use crate::{
+    emulator::{Chip8, Chip8Result},
+    keyboard::{key_mask, Keypad},
+    netplay::{NetplayResult, Session},
+    util::crc32,
+};
+
+// A scripted key press: `key` is held from `frame` for `frames` frames
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyPress {
+    pub frame: u64,
+    pub key: u8,
+    pub frames: u64,
+}
+
+// Keypad fed from a script of key presses instead of a user
+#[derive(Clone, Debug, Default)]
+pub struct ScriptedKeypad {
+    presses: Vec<KeyPress>,
+    frame: u64,
+}
+
+impl ScriptedKeypad {
+    pub fn new(presses: Vec<KeyPress>) -> Self {
+        ScriptedKeypad { presses, frame: 0 }
+    }
+
+    // Parses a comma separated script of FRAME:KEY[:FRAMES] presses, where
+    // KEY is a hex digit and FRAMES defaults to 1, e.g. "60:5:10,200:a"
+    pub fn parse(script: &str) -> Result<Self, String> {
+        let mut presses = Vec::new();
+        for entry in script.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let parts: Vec<&str> = entry.split(':').collect();
+            if parts.len() < 2 || parts.len() > 3 {
+                return Err(format!(
+                    "Invalid key press '{}', expected FRAME:KEY[:FRAMES]",
+                    entry
+                ));
+            }
+            let frame = parts[0]
+                .parse::<u64>()
+                .map_err(|e| format!("Invalid frame in key press '{}': {}", entry, e))?;
+            let key = u8::from_str_radix(parts[1], 16)
+                .ok()
+                .filter(|&k| k < 16)
+                .ok_or_else(|| format!("Invalid key in key press '{}'", entry))?;
+            let frames = match parts.get(2) {
+                Some(n) => n
+                    .parse::<u64>()
+                    .map_err(|e| format!("Invalid duration in key press '{}': {}", entry, e))?,
+                None => 1,
+            };
+            if frame.checked_add(frames).is_none() {
+                return Err(format!("Key press '{}' lasts past the last frame", entry));
+            }
+            presses.push(KeyPress { frame, key, frames });
+        }
+        Ok(ScriptedKeypad::new(presses))
+    }
+
+    // Moves the script to the given frame
+    pub fn set_frame(&mut self, frame: u64) {
+        self.frame = frame;
+    }
+
+    fn held(&self) -> impl Iterator<Item = u8> + '_ {
+        let frame = self.frame;
+        self.presses
+            .iter()
+            .filter(move |p| frame >= p.frame && frame < p.frame.saturating_add(p.frames))
+            .map(|p| p.key)
+    }
+}
+
+impl Keypad for ScriptedKeypad {
+    fn is_pressed(&mut self, key: u8) -> bool {
+        self.held().any(|k| k == key)
+    }
+}
+
+// Runs the machine for a number of frames as fast as possible
+pub fn run(chip8: &mut Chip8, frames: u64, keypad: &mut ScriptedKeypad) -> Chip8Result<()> {
+    for frame in 0..frames {
+        keypad.set_frame(frame);
+        chip8.run_frame(keypad)?;
+    }
+    Ok(())
+}
+
+// Runs the machine for a number of frames in lockstep with a netplay peer
+pub fn run_netplay(
+    chip8: &mut Chip8,
+    frames: u64,
+    keypad: &mut ScriptedKeypad,
+    session: &mut Session,
+) -> NetplayResult<()> {
+    for frame in 0..frames {
+        keypad.set_frame(frame);
+        session.advance(chip8, key_mask(keypad))?;
+    }
+    Ok(())
+}
+
+// Output formats for framebuffer dumps
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DumpFormat {
+    // Rows of '#' (on) and '.' (off)
+    Ascii,
+    // Plain (P1) portable bitmap
+    Pbm,
+    // CRC-32 of the framebuffer as hex
+    Hash,
+}
+
+impl DumpFormat {
+    pub fn parse(name: &str) -> Result<Self, String> {
+        match name {
+            "ascii" => Ok(DumpFormat::Ascii),
+            "pbm" => Ok(DumpFormat::Pbm),
+            "hash" => Ok(DumpFormat::Hash),
+            _ => Err(format!(
+                "Unknown dump format '{}', expected ascii, pbm or hash",
+                name
+            )),
+        }
+    }
+}
+
+// Formats a framebuffer (one byte per pixel) as text
+pub fn dump(buffer: &[u8], width: usize, height: usize, format: DumpFormat) -> String {
+    let mut out = String::new();
+    match format {
+        DumpFormat::Ascii => {
+            for row in buffer.chunks(width).take(height) {
+                out.extend(row.iter().map(|&p| if p > 0 { '#' } else { '.' }));
+                out.push('\n');
+            }
+        }
+        DumpFormat::Pbm => {
+            writeln!(out, "P1\n{} {}", width, height).unwrap();
+            for row in buffer.chunks(width).take(height) {
+                out.extend(row.iter().map(|&p| if p > 0 { '1' } else { '0' }));
+                out.push('\n');
+            }
+        }
+        DumpFormat::Hash => writeln!(out, "{:08x}", framebuffer_hash(buffer)).unwrap(),
+    }
+    out
+}
+
+// Hash identifying the contents of a framebuffer
+pub fn framebuffer_hash(buffer: &[u8]) -> u32 {
+    let bits: Vec<u8> = buffer.iter().map(|&p| (p > 0) as u8).collect();
+    crc32(&bits)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn scripts_key_presses() {
+        let mut keypad = ScriptedKeypad::parse("2:a:3, 5:1").unwrap();
+        let held: Vec<u16> = (0..7)
+            .map(|frame| {
+                keypad.set_frame(frame);
+                key_mask(&mut keypad)
+            })
+            .collect();
+        assert_eq!(held, [0, 0, 1 << 0xa, 1 << 0xa, 1 << 0xa, 1 << 1, 0]);
+
+        assert!(ScriptedKeypad::parse("1:g").is_err());
+        assert!(ScriptedKeypad::parse("1:1:18446744073709551615").is_err());
+
+        // Presses built directly may still run to the end of time
+        let mut keypad = ScriptedKeypad::new(vec![KeyPress {
+            frame: 10,
+            key: 3,
+            frames: u64::MAX,
+        }]);
+        keypad.set_frame(u64::MAX - 1);
+        assert!(keypad.is_pressed(3));
+    }
+}
@@ -1,4 +1,36 @@
-use sdl2::keyboard::Scancode;
+#[cfg(feature = "sdl")]
+use sdl2::{keyboard::Scancode, EventPump};
+
+// Source of CHIP-8 keypad input, implemented by each frontend
+pub trait Keypad {
+    // Whether the key with the given value (0x0-0xF) is held down. FX0A
+    // waits for keys by polling this too, so frontends never block
+    fn is_pressed(&mut self, key: u8) -> bool;
+}
+
+// Keypad holding a bit mask of the held keys, bit N set for key N
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MaskKeypad(pub u16);
+
+impl Keypad for MaskKeypad {
+    fn is_pressed(&mut self, key: u8) -> bool {
+        key < 16 && self.0 & (1 << key) != 0
+    }
+}
+
+// Bit mask of the held keys, bit N set for key N
+pub fn key_mask(keypad: &mut dyn Keypad) -> u16 {
+    (0..16)
+        .filter(|&key| keypad.is_pressed(key))
+        .fold(0, |mask, key| mask | 1 << key)
+}
+
+#[cfg(feature = "sdl")]
+impl Keypad for EventPump {
+    fn is_pressed(&mut self, key: u8) -> bool {
+        self.keyboard_state().is_scancode_pressed(map(key))
+    }
+}
 
 // Converts bytes into scan codes
 // The mapping is done with the following keys:
@@ -6,6 +38,7 @@ use sdl2::keyboard::Scancode;
 // 4 5 6 D      Q W E R
 // 7 8 9 E  =>  A S D F
 // A 0 B F      Z X C V
+#[cfg(feature = "sdl")]
 pub fn map(code: u8) -> Scancode {
     match code {
         0x00 => Scancode::X,
@@ -28,6 +61,7 @@ pub fn map(code: u8) -> Scancode {
     }
 }
 
+#[cfg(feature = "sdl")]
 pub fn unmap(scancode: Scancode) -> Option<u8> {
     match scancode {
         Scancode::X => Some(0x00),
@@ -49,3 +83,27 @@ pub fn unmap(scancode: Scancode) -> Option<u8> {
         _ => None,
     }
 }
+
+// Converts characters typed in a terminal into key values, using the same
+// layout as the scancode mapping
+pub fn unmap_char(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        'x' => Some(0x00),
+        '1' => Some(0x01),
+        '2' => Some(0x02),
+        '3' => Some(0x03),
+        'q' => Some(0x04),
+        'w' => Some(0x05),
+        'e' => Some(0x06),
+        'a' => Some(0x07),
+        's' => Some(0x08),
+        'd' => Some(0x09),
+        'z' => Some(0x0A),
+        'c' => Some(0x0B),
+        '4' => Some(0x0C),
+        'r' => Some(0x0D),
+        'f' => Some(0x0E),
+        'v' => Some(0x0F),
+        _ => None,
+    }
+}
@@ -0,0 +1,90 @@
+#[cfg(feature = "sdl")]
+pub mod audio;
+pub mod batch;
+pub mod bench;
+pub mod cfg;
+pub mod cheat;
+pub mod cpu;
+pub mod debug;
+#[cfg(feature = "sdl")]
+pub mod display;
+pub mod emulator;
+pub mod env;
+pub mod filter;
+pub mod gif;
+pub mod headless;
+pub mod keyboard;
+pub mod lint;
+pub mod netplay;
+pub mod png;
+pub mod remote;
+pub mod screenshot;
+#[cfg(feature = "sdl")]
+pub mod sdl;
+pub mod symbols;
+pub mod time;
+pub mod tone;
+pub mod tui;
+pub mod util;
+pub mod viewport;
+
+// Starting address of user programs
+pub const PROGRAM_LOC: usize = 0x200;
+
+pub const NUM_REGISTERS: usize = 16;
+pub const RAM_SIZE: usize = 4096;
+pub const STACK_SIZE: usize = 64;
+// Largest ROM that fits in memory after PROGRAM_LOC
+pub const MAX_ROM_SIZE: usize = RAM_SIZE - PROGRAM_LOC;
+
+pub const DISPLAY_WIDTH: usize = 64;
+pub const DISPLAY_HEIGHT: usize = 32;
+pub const DISPLAY_LEN: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
+
+// Default foreground color
+pub const DEF_FG_COL: &str = "ABAECB";
+pub const DEF_FG: (u8, u8, u8) = (171, 171, 203);
+// Default background color
+pub const DEF_BG_COL: &str = "101020";
+pub const DEF_BG: (u8, u8, u8) = (16, 16, 32);
+// Default number of instructions per second
+pub const DEF_IPS: u32 = 1000;
+// Fastest emulation speed, one instruction per nanosecond
+pub const MAX_IPS: u32 = 1_000_000_000;
+// Default screen scale factor
+pub const DEF_SCALE: u32 = 10;
+// Default number of frames lit pixels take to fade out
+pub const DEF_FADE_FRAMES: u32 = 4;
+// Default time a key counts as held after a press in the terminal frontend
+pub const DEF_KEY_HOLD_MS: u64 = 200;
+// Default netplay input delay in frames
+pub const DEF_INPUT_DELAY: u32 = 2;
+// Largest netplay input delay, one second of frames
+pub const MAX_INPUT_DELAY: u32 = 60;
+// Default beep frequency (Hz)
+pub const DEF_TONE_FREQ: f32 = 440.0;
+// Default beep volume, from 0 to 1
+pub const DEF_VOLUME: f32 = 0.2;
+// Default time the beep takes to fade in and out (ms)
+pub const DEF_ENVELOPE_MS: u32 = 5;
+// Duration of a 60 Hz frame (ns)
+pub const FRAME_TIME_NS: u128 = 16_666_666;
+
+pub const FONTS: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
@@ -0,0 +1,436 @@
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt::Write,
+};
+
+use crate::{
+    cfg::{self, Cfg, Exit},
+    emulator::{opcode_pattern, Op},
+    symbols::Symbols,
+    PROGRAM_LOC, STACK_SIZE,
+};
+
+// Static checks for common homebrew bugs, run on the control flow graph.
+// The value of I is only followed where every path sets it with ANNN, and
+// returning from a subroutine forgets it, so writes through a computed I
+// are not checked.
+
+// Deepest nesting of calls the interpreter allows before a stack overflow
+const MAX_CALL_DEPTH: usize = STACK_SIZE - 1;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Kind {
+    // Jump or call to an odd address or outside the ROM
+    BadTarget,
+    // Execution running into sprite data or past the end of the ROM
+    FallsIntoData,
+    // FX33 or FX55 writing over the program's own code
+    SelfWrite,
+    // Code nothing branches to
+    Unreachable,
+    // Reachable word that is no instruction
+    UndefinedOpcode,
+    // Calls nesting deeper than the stack, or recursion
+    StackDepth,
+}
+
+impl Kind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Kind::BadTarget => "bad-target",
+            Kind::FallsIntoData => "falls-into-data",
+            Kind::SelfWrite => "self-write",
+            Kind::Unreachable => "unreachable",
+            Kind::UndefinedOpcode => "undefined-opcode",
+            Kind::StackDepth => "stack-depth",
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostic {
+    pub addr: usize,
+    pub kind: Kind,
+    pub message: String,
+}
+
+// Diagnostics of a ROM, by address
+pub fn lint(rom: &[u8]) -> Vec<Diagnostic> {
+    let cfg = cfg::analyze(rom);
+    let mut diagnostics = Vec::new();
+    let mut report = |addr: usize, kind: Kind, message: String| {
+        diagnostics.push(Diagnostic {
+            addr,
+            kind,
+            message,
+        })
+    };
+
+    // Bytes of reachable instructions, and addresses loaded into I
+    let mut code = BTreeSet::new();
+    let mut data = BTreeMap::new();
+    for (addr, decoded) in cfg.blocks.values().flat_map(|b| &b.instructions) {
+        code.insert(*addr);
+        code.insert(*addr + 1);
+        if decoded.op == Op::LdI {
+            data.entry(decoded.nnn as usize).or_insert(*addr);
+        }
+    }
+
+    for (addr, decoded) in cfg.blocks.values().flat_map(|b| &b.instructions) {
+        if opcode_pattern(decoded.instr).is_none() {
+            report(
+                *addr,
+                Kind::UndefinedOpcode,
+                format!("0x{:04X} is not a CHIP-8 instruction", decoded.instr),
+            );
+        }
+        if let Some(&from) = data.get(addr) {
+            report(
+                *addr,
+                Kind::FallsIntoData,
+                format!(
+                    "runs code at 0x{:03x}, used as data at 0x{:03x}",
+                    addr, from
+                ),
+            );
+        }
+    }
+
+    for block in cfg.blocks.values() {
+        let last = block.last();
+        match block.exit {
+            Exit::Jump(target) | Exit::Call { target, .. } => {
+                let what = match block.exit {
+                    Exit::Jump(_) => "jump",
+                    _ => "call",
+                };
+                if target % 2 == 1 {
+                    report(
+                        last,
+                        Kind::BadTarget,
+                        format!("{} to odd address 0x{:03x}", what, target),
+                    );
+                } else if !cfg.in_rom(target) {
+                    report(
+                        last,
+                        Kind::BadTarget,
+                        format!(
+                            "{} to 0x{:03x}, outside the ROM (0x200-0x{:03x})",
+                            what,
+                            target,
+                            cfg.rom_end - 1
+                        ),
+                    );
+                }
+            }
+            _ => (),
+        }
+        let falls_off = match block.exit {
+            Exit::End => true,
+            Exit::Skip { next, skip } => !cfg.in_rom(next) || !cfg.in_rom(skip),
+            Exit::Call { ret, .. } => !cfg.in_rom(ret),
+            _ => false,
+        };
+        if falls_off {
+            report(
+                last,
+                Kind::FallsIntoData,
+                "execution runs past the end of the ROM".to_string(),
+            );
+        }
+    }
+
+    self_writes(&cfg, &code, &mut report);
+    unreachable(&cfg, &code, &data, &mut report);
+    stack_depth(&cfg, &mut report);
+
+    diagnostics.sort_by_key(|d| (d.addr, d.kind));
+    diagnostics
+}
+
+// Value of I at a point of the program
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Index {
+    Known(u16),
+    Varying,
+}
+
+fn merge(a: Option<Index>, b: Index) -> Index {
+    match a {
+        None => b,
+        Some(a) if a == b => a,
+        Some(_) => Index::Varying,
+    }
+}
+
+// Checks FX33 and FX55 wherever I holds a known address
+fn self_writes(cfg: &Cfg, code: &BTreeSet<usize>, report: &mut dyn FnMut(usize, Kind, String)) {
+    // Value of I entering each block, to a fixed point
+    let mut entry: BTreeMap<usize, Index> = BTreeMap::new();
+    if cfg.blocks.contains_key(&PROGRAM_LOC) {
+        entry.insert(PROGRAM_LOC, Index::Known(0));
+    }
+    let mut pending: Vec<usize> = entry.keys().copied().collect();
+    while let Some(start) = pending.pop() {
+        let block = &cfg.blocks[&start];
+        let mut index = entry[&start];
+        for (_, decoded) in &block.instructions {
+            index = match decoded.op {
+                Op::LdI => Index::Known(decoded.nnn),
+                Op::AddI | Op::LdFont => Index::Varying,
+                _ => index,
+            };
+        }
+        let outs: Vec<(usize, Index)> = match block.exit {
+            Exit::Call { target, ret } => vec![(target, index), (ret, Index::Varying)],
+            exit => exit.targets().into_iter().map(|t| (t, index)).collect(),
+        };
+        for (target, index) in outs {
+            if !cfg.blocks.contains_key(&target) {
+                continue;
+            }
+            let merged = merge(entry.get(&target).copied(), index);
+            if entry.get(&target) != Some(&merged) {
+                entry.insert(target, merged);
+                pending.push(target);
+            }
+        }
+    }
+
+    for (start, block) in &cfg.blocks {
+        let mut index = match entry.get(start) {
+            Some(&index) => index,
+            None => continue,
+        };
+        for (addr, decoded) in &block.instructions {
+            let len = match decoded.op {
+                Op::Bcd => 3,
+                Op::Store => decoded.x as usize + 1,
+                Op::LdI => {
+                    index = Index::Known(decoded.nnn);
+                    continue;
+                }
+                Op::AddI | Op::LdFont => {
+                    index = Index::Varying;
+                    continue;
+                }
+                _ => continue,
+            };
+            if let Index::Known(i) = index {
+                let i = i as usize;
+                if let Some(hit) = (i..i + len).find(|a| code.contains(a)) {
+                    report(
+                        *addr,
+                        Kind::SelfWrite,
+                        format!(
+                            "writes 0x{:03x}-0x{:03x} through I, over code at 0x{:03x}",
+                            i,
+                            i + len - 1,
+                            hit
+                        ),
+                    );
+                }
+            }
+        }
+    }
+}
+
+// Reports ROM bytes that are neither reachable code nor data. Data runs
+// from an address loaded into I up to the next code
+fn unreachable(
+    cfg: &Cfg,
+    code: &BTreeSet<usize>,
+    data: &BTreeMap<usize, usize>,
+    report: &mut dyn FnMut(usize, Kind, String),
+) {
+    let mut in_data = false;
+    let mut run: Option<usize> = None;
+    for addr in PROGRAM_LOC..=cfg.rom_end {
+        let end = addr == cfg.rom_end;
+        if data.contains_key(&addr) {
+            in_data = true;
+        }
+        if code.contains(&addr) {
+            in_data = false;
+        }
+        let unused = !end && !in_data && !code.contains(&addr);
+        match (run, unused) {
+            (None, true) => run = Some(addr),
+            (Some(start), false) => {
+                run = None;
+                let size = addr - start;
+                report(
+                    start,
+                    Kind::Unreachable,
+                    format!(
+                        "0x{:03x}-0x{:03x} ({} bytes) is never run nor used as data",
+                        start,
+                        addr - 1,
+                        size
+                    ),
+                );
+            }
+            _ => (),
+        }
+    }
+}
+
+// Longest chain of nested calls from the program start, and recursion
+fn stack_depth(cfg: &Cfg, report: &mut dyn FnMut(usize, Kind, String)) {
+    // Call sites of each subroutine, following its code but not its calls
+    let mut calls: BTreeMap<usize, Vec<(usize, usize)>> = BTreeMap::new();
+    for &entry in cfg.subroutines.keys() {
+        let mut seen = BTreeSet::new();
+        let mut pending = vec![entry];
+        let sites = calls.entry(entry).or_default();
+        while let Some(start) = pending.pop() {
+            if !seen.insert(start) {
+                continue;
+            }
+            let block = &cfg.blocks[&start];
+            let targets = match block.exit {
+                Exit::Call { target, ret } => {
+                    sites.push((block.last(), target));
+                    vec![ret]
+                }
+                exit => exit.targets(),
+            };
+            pending.extend(targets.into_iter().filter(|t| cfg.blocks.contains_key(t)));
+        }
+    }
+
+    let mut deepest = BTreeMap::new();
+    let mut recursive = BTreeSet::new();
+    let path = chain(
+        PROGRAM_LOC,
+        &calls,
+        &mut deepest,
+        &mut Vec::new(),
+        &mut recursive,
+    );
+    for (site, target) in recursive {
+        report(
+            site,
+            Kind::StackDepth,
+            format!(
+                "recursive call to 0x{:03x}, the stack depth has no bound",
+                target
+            ),
+        );
+    }
+    if path.len() > MAX_CALL_DEPTH {
+        let sites: Vec<String> = path.iter().map(|s| format!("0x{:03x}", s)).collect();
+        report(
+            path[MAX_CALL_DEPTH],
+            Kind::StackDepth,
+            format!(
+                "calls nest {} deep, the stack holds {}: {}",
+                path.len(),
+                MAX_CALL_DEPTH,
+                sites.join(" -> ")
+            ),
+        );
+    }
+}
+
+// Call sites of the deepest call chain starting in `entry`
+fn chain(
+    entry: usize,
+    calls: &BTreeMap<usize, Vec<(usize, usize)>>,
+    deepest: &mut BTreeMap<usize, Vec<usize>>,
+    active: &mut Vec<usize>,
+    recursive: &mut BTreeSet<(usize, usize)>,
+) -> Vec<usize> {
+    if let Some(path) = deepest.get(&entry) {
+        return path.clone();
+    }
+    active.push(entry);
+    let mut longest = Vec::new();
+    for &(site, target) in calls.get(&entry).into_iter().flatten() {
+        if active.contains(&target) {
+            recursive.insert((site, target));
+            continue;
+        }
+        let rest = chain(target, calls, deepest, active, recursive);
+        if rest.len() + 1 > longest.len() {
+            longest = std::iter::once(site).chain(rest).collect();
+        }
+    }
+    active.pop();
+    deepest.insert(entry, longest.clone());
+    longest
+}
+
+// Human readable report, one diagnostic per line, with the label and
+// source line of each address when there are symbols
+pub fn summary(diagnostics: &[Diagnostic], symbols: &Symbols) -> String {
+    let mut out = String::new();
+    for diagnostic in diagnostics {
+        let location = symbols.describe(diagnostic.addr);
+        let separator = if location.is_empty() { "" } else { " " };
+        writeln!(
+            out,
+            "0x{:03x}{}{}: {}: {}",
+            diagnostic.addr,
+            separator,
+            location,
+            diagnostic.kind.name(),
+            diagnostic.message
+        )
+        .unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn kinds(program: &[u16]) -> Vec<(usize, Kind)> {
+        let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+        lint(&rom).iter().map(|d| (d.addr, d.kind)).collect()
+    }
+
+    #[test]
+    fn clean_program() {
+        // Draws a sprite and halts
+        assert_eq!(kinds(&[0xA206, 0xD015, 0x1204, 0xF090]), vec![]);
+    }
+
+    #[test]
+    fn finds_bugs() {
+        let program = [
+            0xA210, // 200: I = 0x210
+            0xF033, // 202: BCD over the code at 0x210
+            0x2300, // 204: call outside the ROM
+            0x220E, // 206: call 0x20e
+            0x1208, // 208: halt
+            0x6001, // 20a: never reached
+            0x6002, // 20c: never reached
+            0x5011, // 20e: undefined, runs as a skip
+            0x220E, // 210: recursion, and used as data
+            0x00EE, // 212: return
+        ];
+        assert_eq!(
+            kinds(&program),
+            vec![
+                (0x202, Kind::SelfWrite),
+                (0x204, Kind::BadTarget),
+                (0x20a, Kind::Unreachable),
+                (0x20e, Kind::UndefinedOpcode),
+                (0x210, Kind::FallsIntoData),
+                (0x210, Kind::StackDepth),
+            ]
+        );
+    }
+
+    #[test]
+    fn deep_calls() {
+        // Each subroutine calls the next one
+        let mut program: Vec<u16> = (0..70).map(|n| 0x2202 + 2 * n).collect();
+        program.push(0x00EE);
+        let diagnostics = kinds(&program);
+        assert!(diagnostics.contains(&(0x200 + 2 * MAX_CALL_DEPTH, Kind::StackDepth)));
+    }
+}
@@ -1,142 +1,463 @@
-#![allow(dead_code)]
-
-use std::{error::Error, fs::File, io::Read};
-
-use clap::{App, Arg};
-use sdl2::{event::Event, keyboard::Keycode};
-
-use crate::{audio::Beep, display::Display, emulator::Chip8, util::hex_to_col};
-
-mod audio;
-mod cpu;
-mod debug;
-mod display;
-mod emulator;
-mod keyboard;
-mod time;
-mod util;
-
-// Starting address of user programs
-pub const PROGRAM_LOC: usize = 0x200;
-
-pub const NUM_REGISTERS: usize = 16;
-pub const RAM_SIZE: usize = 4096;
-pub const STACK_SIZE: usize = 64;
-
-pub const DISPLAY_WIDTH: usize = 64;
-pub const DISPLAY_HEIGHT: usize = 32;
-pub const DISPLAY_LEN: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
-
-// Default foreground color
-pub const DEF_FG_COL: &str = "ABAECB";
-pub const DEF_FG: (u8, u8, u8) = (171, 171, 203);
-// Default background color
-pub const DEF_BG_COL: &str = "101020";
-pub const DEF_BG: (u8, u8, u8) = (16, 16, 32);
-// Default number of instructions per second
-pub const DEF_IPS: u32 = 1000;
-// Default screen scale factor
-pub const DEF_SCALE: u32 = 10;
-
-const FONTS: [u8; 80] = [
-    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-    0x20, 0x60, 0x20, 0x20, 0x70, // 1
-    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-];
+use std::{
+    error::Error,
+    fs::{self, File},
+    io::{BufWriter, Write},
+    thread,
+    time::Duration,
+};
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+
+use chip_8::{
+    batch, bench, cfg,
+    cheat::Cheats,
+    emulator::{decode, Addressing, Backend, Chip8, KeyWait, Quirks},
+    headless::{self, DumpFormat, ScriptedKeypad},
+    lint,
+    netplay::{self, Session, Settings},
+    remote::{Remote, RemoteSession},
+    symbols::Symbols,
+    time, tui,
+    util::{crc32, hex_to_col},
+    DEF_BG, DEF_BG_COL, DEF_ENVELOPE_MS, DEF_FADE_FRAMES, DEF_FG, DEF_FG_COL, DEF_INPUT_DELAY,
+    DEF_IPS, DEF_KEY_HOLD_MS, DEF_SCALE, DEF_TONE_FREQ, DEF_VOLUME, DISPLAY_HEIGHT, DISPLAY_WIDTH,
+    FONTS, MAX_INPUT_DELAY, MAX_IPS, MAX_ROM_SIZE,
+};
+#[cfg(feature = "sdl")]
+use chip_8::{
+    display::Persistence,
+    filter::Filters,
+    sdl,
+    tone::{Tone, Waveform},
+    viewport::{Aspect, Scaling},
+};
+
+// Default number of frames run by the headless runner
+const DEF_HEADLESS_FRAMES: u64 = 600;
+// Default number of instructions run by the benchmark
+const DEF_BENCH_INSTRUCTIONS: u64 = 10_000_000;
 
 fn main() -> Result<(), Box<dyn Error>> {
+    let scale_help = format!(
+        "Integer display scale factor, defaults to {} (for 640x320 upscaled resolution)",
+        DEF_SCALE
+    );
+    let ips_help = format!(
+        "Emulation speed in instructions per second, from 1 to {}, defaults to {}",
+        MAX_IPS, DEF_IPS
+    );
+    let fgcol_help = format!(
+        "Foreground (on) color as a hex code, defaults to {}",
+        DEF_FG_COL
+    );
+    let bgcol_help = format!(
+        "Background (off) color as a hex code, defaults to {}",
+        DEF_BG_COL
+    );
+    let fade_frames_help = format!(
+        "Number of frames a pixel takes to fade out with --persistence fade, defaults to {}",
+        DEF_FADE_FRAMES
+    );
+    let key_hold_help = format!(
+        "Milliseconds a key counts as held after a press in the terminal frontend, \
+        which gets no key release events, defaults to {}",
+        DEF_KEY_HOLD_MS
+    );
+    let frames_help = format!(
+        "Number of frames to run in headless mode, defaults to {}",
+        DEF_HEADLESS_FRAMES
+    );
+    let input_delay_help = format!(
+        "Frames local input is delayed by when hosting netplay, hiding network latency, from 0 to {}, defaults to {}",
+        MAX_INPUT_DELAY, DEF_INPUT_DELAY
+    );
+    let bench_instructions_help = format!(
+        "Number of instructions to run, defaults to {}",
+        DEF_BENCH_INSTRUCTIONS
+    );
+    let tone_freq_help = format!("Frequency of the beep in Hz, defaults to {}", DEF_TONE_FREQ);
+    let volume_help = format!(
+        "Volume of the beep from 0 to 1, 0 for no sound, defaults to {}",
+        DEF_VOLUME
+    );
+    let attack_help = format!(
+        "Milliseconds the beep takes to fade in, avoiding clicks, defaults to {}",
+        DEF_ENVELOPE_MS
+    );
+    let release_help = format!(
+        "Milliseconds the beep takes to fade out, avoiding clicks, defaults to {}",
+        DEF_ENVELOPE_MS
+    );
+    let hotkeys = "Press F12 to save a screenshot of the display (native and scaled PNGs) to the current directory, \
+        F9 to start/stop recording an animated GIF, F7 to pause in the cheat console, F8 to mute the sound and F11 or Alt+Enter to toggle fullscreen";
+
+    // Interpreter quirks, shared with `batch`
+    let quirk_args = [
+        Arg::with_name("addressing")
+            .long("addressing")
+            .takes_value(true)
+            .possible_values(&["12bit", "16bit"])
+            .help("Width of the I register: 12bit (wraps at 0xFFF) or 16bit (XO-CHIP), defaults to 12bit"),
+        Arg::with_name("index-overflow")
+            .long("index-overflow")
+            .takes_value(false)
+            .help("FX1E sets VF when I goes past 0xFFF (Amiga interpreter quirk)"),
+        Arg::with_name("key-wait")
+            .long("key-wait")
+            .takes_value(true)
+            .possible_values(&["press", "release"])
+            .help("When FX0A takes a key: press (as soon as it goes down) or release (when it is let go, like the COSMAC VIP), \
+                defaults to release"),
+    ];
+
+    // Execution engine, shared with `bench`
+    let backend_arg = Arg::with_name("backend")
+        .long("backend")
+        .takes_value(true)
+        .possible_values(&["interpreter", "threaded"])
+        .help(
+            "Engine running the ROM: interpreter, or threaded (compiles straight-line code into \
+            threaded code, same results), defaults to interpreter",
+        );
+
+    // Symbol file, shared with `cfg`, `lint` and `bench`
+    let symbols_arg = Arg::with_name("symbols")
+        .long("symbols")
+        .takes_value(true)
+        .value_name("FILE")
+        .help(
+            "Symbol file with labels and source lines of the ROM, shown instead of raw addresses",
+        );
+
+    // Options of the default command, shared with `run`
+    let run_args = [
+        Arg::with_name("input")
+            .required(true)
+            .index(1)
+            .help("ROM file to load and run"),
+        Arg::with_name("debug")
+            .short("d")
+            .long("debug")
+            .takes_value(false)
+            .help("Run in debug mode. Pauses after each instruction, prints info to stdout"),
+        Arg::with_name("scale")
+            .short("s")
+            .long("scale")
+            .takes_value(true)
+            .help(&scale_help),
+        Arg::with_name("ips")
+            .short("i")
+            .long("ips")
+            .takes_value(true)
+            .validator(validate_ips)
+            .help(&ips_help),
+        Arg::with_name("fgcol")
+            .short("c")
+            .long("fgcol")
+            .takes_value(true)
+            .help(&fgcol_help),
+        Arg::with_name("bgcol")
+            .short("b")
+            .long("bgcol")
+            .takes_value(true)
+            .help(&bgcol_help),
+        Arg::with_name("persistence")
+            .short("p")
+            .long("persistence")
+            .takes_value(true)
+            .possible_values(&["off", "fade", "blend"])
+            .help("Display persistence to reduce flicker: off, fade (pixels decay over --fade-frames) or blend (OR the last two frames), defaults to off"),
+        Arg::with_name("fade-frames")
+            .long("fade-frames")
+            .takes_value(true)
+            .help(&fade_frames_help),
+        Arg::with_name("filter")
+            .short("f")
+            .long("filter")
+            .takes_value(true)
+            .multiple(true)
+            .use_delimiter(true)
+            .possible_values(&["scanlines", "grid", "scale2x", "scale3x", "bloom"])
+            .help("Post-processing filters, comma separated. Toggle at runtime with F1 (scanlines), F2 (grid), F3 (cycle scale2x/scale3x) and F4 (bloom)"),
+        Arg::with_name("scaling")
+            .long("scaling")
+            .takes_value(true)
+            .possible_values(&["integer", "fit"])
+            .help("How the display fills a resized window: integer (whole multiples only, pixels all the same size) \
+                or fit (as large as fits), with bars around it, defaults to fit"),
+        Arg::with_name("aspect")
+            .long("aspect")
+            .takes_value(true)
+            .possible_values(&["native", "4:3"])
+            .help("Shape of the display: native (square pixels) or 4:3 (stretched like the original hardware's screens), defaults to native"),
+        Arg::with_name("fullscreen")
+            .long("fullscreen")
+            .takes_value(false)
+            .help("Start in fullscreen. Toggle at runtime with F11 or Alt+Enter"),
+        Arg::with_name("tone-freq")
+            .long("tone-freq")
+            .takes_value(true)
+            .help(&tone_freq_help),
+        Arg::with_name("volume")
+            .long("volume")
+            .takes_value(true)
+            .help(&volume_help),
+        Arg::with_name("waveform")
+            .long("waveform")
+            .takes_value(true)
+            .help("Shape of the beep: square, sine, triangle, noise or the path of a .wav file to loop, defaults to square"),
+        Arg::with_name("attack")
+            .long("attack")
+            .takes_value(true)
+            .help(&attack_help),
+        Arg::with_name("release")
+            .long("release")
+            .takes_value(true)
+            .help(&release_help),
+        Arg::with_name("mute")
+            .long("mute")
+            .takes_value(false)
+            .help("Start with the sound muted. Toggle at runtime with F8"),
+        Arg::with_name("record-gif")
+            .long("record-gif")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Record gameplay to an animated GIF from startup until F9 is pressed or the emulator exits"),
+        Arg::with_name("frontend")
+            .long("frontend")
+            .takes_value(true)
+            .possible_values(&["sdl", "tui"])
+            .help("Frontend to run in: sdl (window) or tui (terminal, no display needed), defaults to sdl"),
+        Arg::with_name("key-hold")
+            .long("key-hold")
+            .takes_value(true)
+            .help(&key_hold_help),
+        Arg::with_name("cheats")
+            .long("cheats")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Cheat file with values to freeze, keyed by ROM hash. Press F7 in the window for the cheat console"),
+        Arg::with_name("host")
+            .long("host")
+            .takes_value(true)
+            .value_name("PORT")
+            .conflicts_with("join")
+            .help("Host a two player netplay session, waiting for a peer to join on PORT"),
+        Arg::with_name("join")
+            .long("join")
+            .takes_value(true)
+            .value_name("ADDR")
+            .help("Join the netplay session hosted at ADDR (host:port), with the host's speed, seed and quirks"),
+        Arg::with_name("input-delay")
+            .long("input-delay")
+            .takes_value(true)
+            .value_name("FRAMES")
+            .help(&input_delay_help),
+        Arg::with_name("remote")
+            .long("remote")
+            .takes_value(true)
+            .value_name("TARGET")
+            .conflicts_with_all(&["host", "join"])
+            .help("Let another process drive the machine with line-delimited JSON commands over \
+                stdin/stdout (stdio) or a local TCP port. The machine only runs when told to. Headless runs default the seed to 0"),
+        backend_arg.clone(),
+        symbols_arg.clone(),
+        Arg::with_name("trace")
+            .long("trace")
+            .takes_value(true)
+            .value_name("FILE")
+            .help("Write every instruction run to FILE, with its address, disassembly, label and source line"),
+        Arg::with_name("seed")
+            .long("seed")
+            .takes_value(true)
+            .help("Seed of the random number generator used by CXNN, for reproducible runs. Headless runs default to 0"),
+    ];
+
     let matches = App::new("chip-8")
         .version("0.1.0")
         .about("chip-8 emulator")
-        .arg(
-            Arg::with_name("input")
-                .required(true)
-                .index(1)
-                .help("ROM file to load and run"),
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .args(&run_args)
+        .args(&quirk_args)
+        .after_help(hotkeys)
+        .subcommand(
+            SubCommand::with_name("run")
+                .about("Runs a ROM, in a window, in the terminal or headless")
+                .args(&run_args)
+                .args(&quirk_args)
+                .arg(
+                    Arg::with_name("headless")
+                        .long("headless")
+                        .takes_value(false)
+                        .help("Run without any display or input as fast as possible, then dump the final framebuffer"),
+                )
+                .arg(
+                    Arg::with_name("frames")
+                        .long("frames")
+                        .takes_value(true)
+                        .help(&frames_help),
+                )
+                .arg(
+                    Arg::with_name("dump")
+                        .long("dump")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("File the final framebuffer is written to in headless mode, defaults to stdout"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["ascii", "pbm", "hash"])
+                        .help("Framebuffer dump format: ascii art, pbm image or hash, defaults to ascii"),
+                )
+                .arg(
+                    Arg::with_name("keys")
+                        .long("keys")
+                        .takes_value(true)
+                        .value_name("SCRIPT")
+                        .help("Key presses fed in headless mode, as comma separated FRAME:KEY[:FRAMES] \
+                            entries with KEY a hex digit held for FRAMES frames (default 1), e.g. 60:5:10,200:a"),
+                )
+                .after_help(hotkeys),
         )
-        .arg(
-            Arg::with_name("debug")
-                .short("d")
-                .long("debug")
-                .takes_value(false)
-                .help("Run in debug mode. Pauses after each instruction, prints info to stdout"),
+        .subcommand(
+            SubCommand::with_name("batch")
+                .about("Smoke tests every .ch8 ROM of a directory headlessly, reporting crashes, halts, unknown opcodes and final frame hashes")
+                .arg(
+                    Arg::with_name("dir")
+                        .required(true)
+                        .index(1)
+                        .help("Directory of ROMs to run"),
+                )
+                .arg(
+                    Arg::with_name("frames")
+                        .long("frames")
+                        .takes_value(true)
+                        .help(&frames_help),
+                )
+                .arg(
+                    Arg::with_name("threads")
+                        .long("threads")
+                        .takes_value(true)
+                        .help("Number of ROMs run in parallel, defaults to the number of CPUs"),
+                )
+                .arg(
+                    Arg::with_name("format")
+                        .long("format")
+                        .takes_value(true)
+                        .possible_values(&["table", "json"])
+                        .help("Report format: table or json, defaults to table"),
+                )
+                .args(&quirk_args),
         )
-        .arg(
-            Arg::with_name("scale")
-                .short("s")
-                .long("scale")
-                .takes_value(true)
-                .help(&format!("Integer display scale factor, defaults to {} (for 640x320 upscaled resolution)", DEF_SCALE)),
+        .subcommand(
+            SubCommand::with_name("bench")
+                .about("Runs a ROM unthrottled without rendering and reports interpreter throughput with a per-opcode breakdown")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .index(1)
+                        .help("ROM file to benchmark"),
+                )
+                .arg(
+                    Arg::with_name("instructions")
+                        .long("instructions")
+                        .takes_value(true)
+                        .help(&bench_instructions_help),
+                )
+                .arg(
+                    Arg::with_name("duration")
+                        .long("duration")
+                        .takes_value(true)
+                        .value_name("SECONDS")
+                        .conflicts_with("instructions")
+                        .help("Run for a number of seconds instead of a number of instructions. The instrumented \
+                            run for the opcode breakdown gets the same time, so the command takes up to twice as long"),
+                )
+                .arg(
+                    Arg::with_name("ips")
+                        .short("i")
+                        .long("ips")
+                        .takes_value(true)
+                        .validator(validate_ips)
+                        .help(&ips_help),
+                )
+                .arg(backend_arg)
+                .arg(symbols_arg.clone())
+                .args(&quirk_args),
         )
-        .arg(
-            Arg::with_name("ips")
-                .short("i")
-                .long("ips")
-                .takes_value(true)
-                .help(&format!("Emulation speed in instructions per second, defaults to {}", DEF_IPS)),
+        .subcommand(
+            SubCommand::with_name("cfg")
+                .about("Writes the control flow graph of a ROM as a Graphviz DOT graph, with the disassembly of each basic block")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .index(1)
+                        .help("ROM file to analyze"),
+                )
+                .arg(
+                    Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .value_name("FILE")
+                        .help("File the graph is written to, defaults to stdout"),
+                )
+                .arg(symbols_arg.clone()),
         )
-        .arg(
-            Arg::with_name("fgcol")
-                .short("c")
-                .long("fgcol")
-                .takes_value(true)
-                .help(&format!("Foreground (on) color as a hex code, defaults to {}", DEF_FG_COL)),
-        )
-        .arg(
-            Arg::with_name("bgcol")
-                .short("b")
-                .long("bgcol")
-                .takes_value(true)
-                .help(&format!("Background (off) color as a hex code, defaults to {}", DEF_BG_COL)),
+        .subcommand(
+            SubCommand::with_name("lint")
+                .about("Checks a ROM for common bugs without running it: bad jump targets, execution falling into data, \
+                    writes over code, unreachable code, undefined opcodes and stack overflows")
+                .arg(
+                    Arg::with_name("input")
+                        .required(true)
+                        .index(1)
+                        .help("ROM file to check"),
+                )
+                .arg(symbols_arg),
         )
         .get_matches();
 
+    match matches.subcommand() {
+        ("run", Some(sub_matches)) => run(sub_matches),
+        ("batch", Some(sub_matches)) => run_batch(sub_matches),
+        ("bench", Some(sub_matches)) => run_bench(sub_matches),
+        ("cfg", Some(sub_matches)) => run_cfg(sub_matches),
+        ("lint", Some(sub_matches)) => run_lint(sub_matches),
+        _ => run(&matches),
+    }
+}
+
+// Loads the ROM and runs it with the selected frontend
+fn run(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let headless = matches.is_present("headless");
+    let remote = matches.value_of("remote");
+    // Only the dump or the remote replies go to stdout
+    let quiet = headless || remote == Some("stdio");
+
     let filename = matches.value_of("input").unwrap();
     // Read ROM
-    println!("Reading ROM file: {}", filename);
-    let mut f = File::open(filename).unwrap();
-    let mut rom = Vec::new();
-    f.read_to_end(&mut rom).unwrap();
-
-    // Scaling
-    let def_scale: &str = &DEF_SCALE.to_string();
-    let scl_str = matches.value_of("scale").unwrap_or(def_scale);
-    let scl_res = scl_str.parse::<u32>();
-    let mut scale: u32 = DEF_SCALE;
-    match scl_res {
-        Ok(n) => scale = n,
-        Err(e) => println!(
-            "The scale ({}) is not a valid unsigned integer, using default: {}",
-            scl_str, e
-        ),
+    if !quiet {
+        println!("Reading ROM file: {}", filename);
+    }
+    let rom = fs::read(filename)?;
+    let rom_hash = crc32(&rom);
+    if !quiet {
+        println!("ROM hash: {:08x}", rom_hash);
+    }
+    // A warning, kept off stdout so it cannot break dumps or remote replies
+    if rom.len() > MAX_ROM_SIZE {
+        eprintln!(
+            "The ROM is {} bytes, only the first {} fit in memory",
+            rom.len(),
+            MAX_ROM_SIZE
+        );
     }
 
     // Emulation speed
-    let default_ips: &str = &DEF_IPS.to_string();
-    let ips_str = matches.value_of("ips").unwrap_or(default_ips);
-    let ips_res = ips_str.parse::<u32>();
-    let mut ips: u32 = DEF_IPS;
-    match ips_res {
-        Ok(n) => ips = n,
-        Err(e) => println!(
-            "The ips ({}) is not a valid unsigned integer, using default: {}",
-            ips_str, e
-        ),
-    }
-    let instruction_time_ns: u128 = (1e9 as u128 / ips as u128) as u128;
+    let mut instruction_time_ns: u128 = 1e9 as u128 / parse_ips(matches) as u128;
 
     // Foreground color
     let fg_str = matches.value_of("fgcol").unwrap_or(DEF_FG_COL);
@@ -160,62 +481,499 @@ fn main() -> Result<(), Box<dyn Error>> {
         }
     };
 
-    // Start time
-    let start: u128 = time::time_nanos();
-
-    println!("chip-8 starting");
+    let mut quirks = parse_quirks(matches);
 
-    // Init SDL2
-    let sdl_context = sdl2::init().unwrap();
+    // Random seed
+    let mut seed = match matches.value_of("seed") {
+        Some(seed_str) => Some(seed_str.parse::<u64>().map_err(|e| {
+            format!(
+                "The seed ({}) is not a valid unsigned integer: {}",
+                seed_str, e
+            )
+        })?),
+        None if headless || remote.is_some() => Some(0),
+        None => None,
+    };
 
-    // Create the display
-    let mut display = Display::new(&sdl_context, "R-CHIP-8", scale, fgcol, bgcol);
+    // Netplay, the joining peer takes the host's settings
+    let mut netplay = if let Some(port_str) = matches.value_of("host") {
+        let port = port_str
+            .parse::<u16>()
+            .map_err(|e| format!("The port ({}) is not a valid port: {}", port_str, e))?;
+        let def_delay: &str = &DEF_INPUT_DELAY.to_string();
+        let delay_str = matches.value_of("input-delay").unwrap_or(def_delay);
+        let delay = match delay_str.parse::<u32>() {
+            Ok(n) if n <= MAX_INPUT_DELAY => n,
+            _ => {
+                println!(
+                    "The input delay ({}) is not an integer from 0 to {}, using default: {}",
+                    delay_str, MAX_INPUT_DELAY, DEF_INPUT_DELAY
+                );
+                DEF_INPUT_DELAY
+            }
+        };
+        let settings = Settings {
+            rom_hash,
+            seed: seed.unwrap_or(time::time_nanos() as u64),
+            delay,
+            instruction_time_ns,
+            quirks,
+        };
+        seed = Some(settings.seed);
+        Some(netplay::host(port, &settings)?)
+    } else if let Some(addr) = matches.value_of("join") {
+        let (session, settings) = netplay::join(addr, rom_hash)?;
+        seed = Some(settings.seed);
+        instruction_time_ns = settings.instruction_time_ns;
+        quirks = settings.quirks;
+        Some(session)
+    } else {
+        None
+    };
+    if netplay.is_some() && matches.is_present("cheats") {
+        return Err("Cheats are not available in netplay, they would desync the peers".into());
+    }
 
-    // Create audio beep
-    let beep = Beep::new(&sdl_context);
+    // Start time
+    let start: u128 = time::time_nanos();
 
     // Create the machine
     let debug_mode = matches.occurrences_of("debug") > 0;
-    println!("Debug: {}", debug_mode);
+    if !quiet {
+        println!("chip-8 starting");
+        println!("Debug: {}", debug_mode);
+    }
+    let mut remote = match remote {
+        Some(target) => {
+            let session = RemoteSession::new(rom.clone(), quirks, seed.unwrap_or(0));
+            Some(Remote::open(target, session)?)
+        }
+        None => None,
+    };
     let mut chip8 = Chip8::new(rom, FONTS, start, instruction_time_ns, debug_mode);
+    chip8.quirks = quirks;
+    chip8.backend = parse_backend(matches);
+    chip8.symbols = parse_symbols(matches);
+    if let Some(path) = matches.value_of("trace") {
+        trace(&mut chip8, path)?;
+    }
+    if let Some(path) = matches.value_of("cheats") {
+        match Cheats::load(path, rom_hash) {
+            Ok(cheats) => chip8.cheats = cheats,
+            Err(error) => println!("{}", error),
+        }
+    }
+    if let Some(seed) = seed {
+        chip8.seed(seed);
+    }
+
+    if let (true, Some(remote)) = (headless, remote.as_mut()) {
+        while remote.poll(&mut chip8, true)? {}
+        return Ok(());
+    }
+    if headless {
+        return run_headless(&mut chip8, matches, netplay.as_mut());
+    }
+    if (netplay.is_some() || remote.is_some()) && matches.value_of("frontend") == Some("tui") {
+        return Err("Netplay and remote control need the sdl frontend or --headless".into());
+    }
 
-    // Main loop
-    'mainloop: loop {
-        let t: u128 = time::time_nanos();
-
-        // Event loop
-        for event in display.event_pump.poll_iter() {
-            match event {
-                Event::Quit { .. }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::Escape),
-                    ..
+    match matches.value_of("frontend").unwrap_or("sdl") {
+        "tui" => {
+            // Key hold time of the terminal frontend
+            let def_key_hold: &str = &DEF_KEY_HOLD_MS.to_string();
+            let key_hold_str = matches.value_of("key-hold").unwrap_or(def_key_hold);
+            let key_hold_ms = match key_hold_str.parse::<u64>() {
+                Ok(n) => n,
+                Err(e) => {
+                    println!(
+                        "The key hold time ({}) is not a valid unsigned integer, using default: {}",
+                        key_hold_str, e
+                    );
+                    DEF_KEY_HOLD_MS
                 }
-                | Event::KeyDown {
-                    keycode: Some(Keycode::CapsLock),
-                    ..
-                } => break 'mainloop,
-                _ => {}
+            };
+            tui::run(&mut chip8, fgcol, bgcol, key_hold_ms)
+        }
+        _ => run_sdl(
+            &mut chip8,
+            matches,
+            rom_hash,
+            fgcol,
+            bgcol,
+            netplay.as_mut(),
+            remote.as_mut(),
+        ),
+    }
+}
+
+#[cfg(feature = "sdl")]
+fn run_sdl(
+    chip8: &mut Chip8,
+    matches: &ArgMatches,
+    rom_hash: u32,
+    fgcol: (u8, u8, u8),
+    bgcol: (u8, u8, u8),
+    netplay: Option<&mut Session>,
+    remote: Option<&mut Remote>,
+) -> Result<(), Box<dyn Error>> {
+    // Scaling
+    let def_scale: &str = &DEF_SCALE.to_string();
+    let scl_str = matches.value_of("scale").unwrap_or(def_scale);
+    let scl_res = scl_str.parse::<u32>();
+    let mut scale: u32 = DEF_SCALE;
+    match scl_res {
+        Ok(n) => scale = n,
+        Err(e) => println!(
+            "The scale ({}) is not a valid unsigned integer, using default: {}",
+            scl_str, e
+        ),
+    }
+
+    // Display persistence
+    let def_fade_frames: &str = &DEF_FADE_FRAMES.to_string();
+    let fade_str = matches.value_of("fade-frames").unwrap_or(def_fade_frames);
+    let fade_frames = match fade_str.parse::<u32>() {
+        Ok(n) => n,
+        Err(e) => {
+            println!(
+                "The fade frames ({}) is not a valid unsigned integer, using default: {}",
+                fade_str, e
+            );
+            DEF_FADE_FRAMES
+        }
+    };
+    let persistence_str = matches.value_of("persistence").unwrap_or("off");
+    let persistence = match Persistence::parse(persistence_str, fade_frames) {
+        Ok(persistence) => persistence,
+        Err(error) => {
+            println!("{}", error);
+            Persistence::Off
+        }
+    };
+
+    // Post-processing filters
+    let filters = match Filters::parse(matches.values_of("filter").into_iter().flatten()) {
+        Ok(filters) => filters,
+        Err(error) => {
+            println!("{}", error);
+            Filters::default()
+        }
+    };
+
+    // Window scaling
+    let scaling = match Scaling::parse(matches.value_of("scaling").unwrap_or("fit")) {
+        Ok(scaling) => scaling,
+        Err(error) => {
+            println!("{}", error);
+            Scaling::Fit
+        }
+    };
+    let aspect = match Aspect::parse(matches.value_of("aspect").unwrap_or("native")) {
+        Ok(aspect) => aspect,
+        Err(error) => {
+            println!("{}", error);
+            Aspect::Native
+        }
+    };
+
+    let options = sdl::Options {
+        tone: parse_tone(matches),
+        muted: matches.is_present("mute"),
+        rom: matches.value_of("input").unwrap(),
+        scale,
+        fgcol,
+        bgcol,
+        persistence,
+        filters,
+        scaling,
+        aspect,
+        fullscreen: matches.is_present("fullscreen"),
+        record_gif: matches.value_of("record-gif"),
+        rom_hash,
+        cheat_file: matches.value_of("cheats"),
+    };
+    let driver = match (netplay, remote) {
+        (Some(session), _) => sdl::Driver::Netplay(session),
+        (None, Some(remote)) => sdl::Driver::Remote(remote),
+        (None, None) => sdl::Driver::Clock,
+    };
+    sdl::run(chip8, &options, driver)
+}
+
+// Beep settings, invalid values fall back to the defaults
+#[cfg(feature = "sdl")]
+fn parse_tone(matches: &ArgMatches) -> Tone {
+    let mut tone = Tone::default();
+
+    if let Some(freq_str) = matches.value_of("tone-freq") {
+        match freq_str.parse::<f32>() {
+            Ok(f) if f > 0.0 && f.is_finite() => tone.frequency = f,
+            _ => println!(
+                "The tone frequency ({}) is not a positive number, using default: {}",
+                freq_str, DEF_TONE_FREQ
+            ),
+        }
+    }
+    if let Some(volume_str) = matches.value_of("volume") {
+        match volume_str.parse::<f32>() {
+            Ok(v) if (0.0..=1.0).contains(&v) => tone.volume = v,
+            _ => println!(
+                "The volume ({}) is not a number from 0 to 1, using default: {}",
+                volume_str, DEF_VOLUME
+            ),
+        }
+    }
+    if let Some(waveform_str) = matches.value_of("waveform") {
+        match Waveform::parse(waveform_str) {
+            Ok(waveform) => tone.waveform = waveform,
+            Err(error) => println!("{}, using square", error),
+        }
+    }
+    for (name, ms) in [
+        ("attack", &mut tone.attack_ms),
+        ("release", &mut tone.release_ms),
+    ] {
+        if let Some(ms_str) = matches.value_of(name) {
+            match ms_str.parse::<u32>() {
+                Ok(n) => *ms = n,
+                Err(e) => println!(
+                    "The {} ({}) is not a valid unsigned integer, using default: {}",
+                    name, ms_str, e
+                ),
             }
         }
+    }
+    tone
+}
+
+#[cfg(not(feature = "sdl"))]
+fn run_sdl(
+    _chip8: &mut Chip8,
+    _matches: &ArgMatches,
+    _rom_hash: u32,
+    _fgcol: (u8, u8, u8),
+    _bgcol: (u8, u8, u8),
+    _netplay: Option<&mut Session>,
+    _remote: Option<&mut Remote>,
+) -> Result<(), Box<dyn Error>> {
+    Err("Built without SDL support, use --frontend tui or run --headless".into())
+}
 
-        // Run the machine
-        chip8.cycle(t, &mut display.event_pump);
+// Runs the machine without a display, then dumps the final framebuffer.
+// Emulator errors are returned after the dump, for a nonzero exit status
+fn run_headless(
+    chip8: &mut Chip8,
+    matches: &ArgMatches,
+    netplay: Option<&mut Session>,
+) -> Result<(), Box<dyn Error>> {
+    let frames = parse_frames(matches)?;
+    let format = DumpFormat::parse(matches.value_of("format").unwrap_or("ascii"))?;
+    let mut keypad = ScriptedKeypad::parse(matches.value_of("keys").unwrap_or(""))?;
 
-        // Clear/update display if needed
-        if chip8.display_clear_flag {
-            display.clear();
+    let result: Result<(), Box<dyn Error>> = match netplay {
+        Some(session) => {
+            headless::run_netplay(chip8, frames, &mut keypad, session).map_err(|e| e.into())
         }
-        if chip8.display_update_flag {
-            display.render(chip8.display);
+        None => headless::run(chip8, frames, &mut keypad).map_err(|e| e.into()),
+    };
+
+    let dump = headless::dump(&chip8.display, DISPLAY_WIDTH, DISPLAY_HEIGHT, format);
+    match matches.value_of("dump") {
+        Some(path) if path != "-" => fs::write(path, dump)?,
+        _ => print!("{}", dump),
+    }
+
+    result
+}
+
+// Runs every ROM of a directory headlessly and prints a report. Crashes
+// are returned after the report, for a nonzero exit status
+fn run_batch(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let frames = parse_frames(matches)?;
+    let threads = match matches.value_of("threads") {
+        Some(threads_str) => threads_str.parse::<usize>().map_err(|e| {
+            format!(
+                "The threads ({}) is not a valid unsigned integer: {}",
+                threads_str, e
+            )
+        })?,
+        None => thread::available_parallelism().map_or(1, |n| n.get()),
+    };
+    let quirks = parse_quirks(matches);
+
+    let roms = batch::load_dir(matches.value_of("dir").unwrap())?;
+    let reports = batch::run_all(roms, frames, quirks, threads);
+    match matches.value_of("format").unwrap_or("table") {
+        "json" => println!("{}", batch::json(&reports)),
+        _ => print!("{}", batch::table(&reports)),
+    }
+
+    let crashed = reports
+        .iter()
+        .filter(|r| matches!(r.outcome, batch::Outcome::Crashed { .. }))
+        .count();
+    if crashed > 0 {
+        return Err(format!("{} of {} ROMs crashed", crashed, reports.len()).into());
+    }
+    Ok(())
+}
+
+// Benchmarks the interpreter on a ROM. Errors that stop the ROM are part
+// of the report
+fn run_bench(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let rom = fs::read(matches.value_of("input").unwrap())?;
+    let limit = match (
+        matches.value_of("duration"),
+        matches.value_of("instructions"),
+    ) {
+        (Some(secs_str), _) => {
+            let secs = secs_str
+                .parse::<f64>()
+                .ok()
+                .filter(|s| s.is_finite() && *s >= 0.0)
+                .ok_or_else(|| {
+                    format!(
+                        "The duration ({}) is not a valid number of seconds",
+                        secs_str
+                    )
+                })?;
+            bench::Limit::Duration(Duration::from_secs_f64(secs))
         }
+        (None, Some(n_str)) => bench::Limit::Instructions(n_str.parse::<u64>().map_err(|e| {
+            format!(
+                "The instructions ({}) is not a valid unsigned integer: {}",
+                n_str, e
+            )
+        })?),
+        (None, None) => bench::Limit::Instructions(DEF_BENCH_INSTRUCTIONS),
+    };
+    let instruction_time_ns = 1e9 as u128 / parse_ips(matches) as u128;
+
+    let report = bench::run(
+        &rom,
+        instruction_time_ns,
+        parse_quirks(matches),
+        parse_backend(matches),
+        limit,
+    );
+    print!(
+        "{}",
+        bench::summary(&report, instruction_time_ns, &parse_symbols(matches))
+    );
+    Ok(())
+}
+
+fn parse_frames(matches: &ArgMatches) -> Result<u64, String> {
+    match matches.value_of("frames") {
+        Some(frames_str) => frames_str.parse::<u64>().map_err(|e| {
+            format!(
+                "The frames ({}) is not a valid unsigned integer: {}",
+                frames_str, e
+            )
+        }),
+        None => Ok(DEF_HEADLESS_FRAMES),
+    }
+}
+
+// Speeds that give an instruction time of at least 1 ns
+fn validate_ips(ips: String) -> Result<(), String> {
+    match ips.parse::<u32>() {
+        Ok(n) if (1..=MAX_IPS).contains(&n) => Ok(()),
+        _ => Err(format!(
+            "The ips ({}) must be an integer from 1 to {}",
+            ips, MAX_IPS
+        )),
+    }
+}
 
-        // Play/pause the beep
-        if chip8.beep_flag {
-            beep.play();
-        } else {
-            beep.pause();
+// Emulation speed, already checked by `validate_ips`
+fn parse_ips(matches: &ArgMatches) -> u32 {
+    matches
+        .value_of("ips")
+        .map_or(DEF_IPS, |ips| ips.parse().unwrap())
+}
+
+fn parse_quirks(matches: &ArgMatches) -> Quirks {
+    let addressing_str = matches.value_of("addressing").unwrap_or("12bit");
+    let addressing = match Addressing::parse(addressing_str) {
+        Ok(addressing) => addressing,
+        Err(error) => {
+            println!("{}", error);
+            Addressing::Wrap12
+        }
+    };
+    let key_wait = match KeyWait::parse(matches.value_of("key-wait").unwrap_or("release")) {
+        Ok(key_wait) => key_wait,
+        Err(error) => {
+            println!("{}", error);
+            KeyWait::Release
         }
+    };
+    Quirks {
+        addressing,
+        index_overflow: matches.is_present("index-overflow"),
+        key_wait,
+    }
+}
+
+// Writes the control flow graph of a ROM
+fn run_cfg(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let rom = fs::read(matches.value_of("input").unwrap())?;
+    let dot = cfg::dot(&cfg::analyze(&rom), &parse_symbols(matches));
+    match matches.value_of("output") {
+        Some(path) => fs::write(path, dot)?,
+        None => print!("{}", dot),
+    }
+    Ok(())
+}
+
+// Checks a ROM, failing if anything was found
+fn run_lint(matches: &ArgMatches) -> Result<(), Box<dyn Error>> {
+    let rom = fs::read(matches.value_of("input").unwrap())?;
+    let diagnostics = lint::lint(&rom);
+    print!("{}", lint::summary(&diagnostics, &parse_symbols(matches)));
+    if !diagnostics.is_empty() {
+        return Err(format!("Problems found: {}", diagnostics.len()).into());
     }
     Ok(())
 }
+
+fn parse_symbols(matches: &ArgMatches) -> Symbols {
+    match matches.value_of("symbols") {
+        Some(path) => match Symbols::load(path) {
+            Ok(symbols) => symbols,
+            Err(error) => {
+                println!("{}", error);
+                Symbols::default()
+            }
+        },
+        None => Symbols::default(),
+    }
+}
+
+// Writes each instruction before it runs
+fn trace(chip8: &mut Chip8, path: &str) -> Result<(), Box<dyn Error>> {
+    let mut file = BufWriter::new(File::create(path)?);
+    chip8.on_before_instruction(move |chip8, instr| {
+        let line = format!(
+            "{:03x}  {:04x}  {:24}  {}",
+            chip8.pc,
+            instr,
+            chip8.symbols.disassemble(&decode(instr)),
+            chip8.symbols.describe(chip8.pc)
+        );
+        let _ = writeln!(file, "{}", line.trim_end());
+    });
+    Ok(())
+}
+
+fn parse_backend(matches: &ArgMatches) -> Backend {
+    match Backend::parse(matches.value_of("backend").unwrap_or("interpreter")) {
+        Ok(backend) => backend,
+        Err(error) => {
+            println!("{}", error);
+            Backend::Interpreter
+        }
+    }
+}
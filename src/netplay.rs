@@ -0,0 +1,511 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt::Display,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, ToSocketAddrs},
+};
+
+use crate::{
+    emulator::{Addressing, Chip8, Chip8Error, KeyWait, Quirks, Status},
+    keyboard::MaskKeypad,
+    util::crc32,
+    MAX_INPUT_DELAY,
+};
+
+// Two player netplay over TCP. Both peers run the same deterministic
+// machine and exchange their keypad state every frame. A frame only runs
+// once the input of both peers for it is known, and the keys the machine
+// sees are the union of both keypads.
+//
+// Local input is scheduled `delay` frames ahead, so a peer only stalls if
+// the other falls more than `delay` frames behind. Each input message also
+// carries a checksum of the sender's machine, compared against the local
+// machine at the same frame to detect desyncs.
+
+const MAGIC: &[u8; 4] = b"C8NP";
+const VERSION: u8 = 2;
+
+// Length of the host hello: magic, version, ROM hash, seed, input delay,
+// instruction time and quirks
+const HELLO_LEN: usize = 4 + 1 + 4 + 8 + 4 + 8 + 3;
+// Length of the join reply: magic and ROM hash
+const REPLY_LEN: usize = 4 + 4;
+// Length of an input message: frame, keys and checksum
+const INPUT_LEN: usize = 8 + 2 + 4;
+
+#[derive(Debug)]
+pub enum NetplayError {
+    Io(io::Error),
+    Handshake(String),
+    Desync(u64),
+    Emulator(Chip8Error),
+}
+
+impl Error for NetplayError {}
+
+impl Display for NetplayError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self {
+            NetplayError::Io(e) => write!(f, "Netplay connection error: {}", e),
+            NetplayError::Handshake(msg) => write!(f, "Netplay handshake failed: {}", msg),
+            NetplayError::Desync(frame) => {
+                write!(f, "Netplay desync detected at frame {}.", frame)
+            }
+            NetplayError::Emulator(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<io::Error> for NetplayError {
+    fn from(e: io::Error) -> Self {
+        NetplayError::Io(e)
+    }
+}
+
+impl From<Chip8Error> for NetplayError {
+    fn from(e: Chip8Error) -> Self {
+        NetplayError::Emulator(e)
+    }
+}
+
+pub type NetplayResult<T> = Result<T, NetplayError>;
+
+// Settings the host sends so both machines behave the same
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Settings {
+    pub rom_hash: u32,
+    pub seed: u64,
+    pub delay: u32,
+    pub instruction_time_ns: u128,
+    pub quirks: Quirks,
+}
+
+impl Settings {
+    fn encode(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(HELLO_LEN);
+        out.extend_from_slice(MAGIC);
+        out.push(VERSION);
+        out.extend_from_slice(&self.rom_hash.to_be_bytes());
+        out.extend_from_slice(&self.seed.to_be_bytes());
+        out.extend_from_slice(&self.delay.to_be_bytes());
+        out.extend_from_slice(&(self.instruction_time_ns as u64).to_be_bytes());
+        out.push(match self.quirks.addressing {
+            Addressing::Wrap12 => 0,
+            Addressing::Bits16 => 1,
+        });
+        out.push(self.quirks.index_overflow as u8);
+        out.push(match self.quirks.key_wait {
+            KeyWait::Press => 0,
+            KeyWait::Release => 1,
+        });
+        out
+    }
+
+    fn decode(data: &[u8; HELLO_LEN]) -> NetplayResult<Self> {
+        if &data[..4] != MAGIC {
+            return Err(NetplayError::Handshake("peer is not a chip-8 host".into()));
+        }
+        if data[4] != VERSION {
+            return Err(NetplayError::Handshake(format!(
+                "host uses netplay version {}, expected {}",
+                data[4], VERSION
+            )));
+        }
+        let delay = u32::from_be_bytes([data[17], data[18], data[19], data[20]]);
+        if delay > MAX_INPUT_DELAY {
+            return Err(NetplayError::Handshake(format!(
+                "input delay of {} frames is above the limit of {}",
+                delay, MAX_INPUT_DELAY
+            )));
+        }
+        let instruction_time_ns = u64::from_be_bytes(bytes(&data[21..29])) as u128;
+        if instruction_time_ns == 0 {
+            return Err(NetplayError::Handshake(
+                "instruction time must not be zero".into(),
+            ));
+        }
+        let addressing = match data[29] {
+            0 => Addressing::Wrap12,
+            1 => Addressing::Bits16,
+            n => {
+                return Err(NetplayError::Handshake(format!(
+                    "unknown addressing mode {}",
+                    n
+                )))
+            }
+        };
+        let key_wait = match data[31] {
+            0 => KeyWait::Press,
+            1 => KeyWait::Release,
+            n => {
+                return Err(NetplayError::Handshake(format!(
+                    "unknown key wait mode {}",
+                    n
+                )))
+            }
+        };
+        Ok(Settings {
+            rom_hash: u32::from_be_bytes([data[5], data[6], data[7], data[8]]),
+            seed: u64::from_be_bytes(bytes(&data[9..17])),
+            delay,
+            instruction_time_ns,
+            quirks: Quirks {
+                addressing,
+                index_overflow: data[30] != 0,
+                key_wait,
+            },
+        })
+    }
+}
+
+fn bytes(slice: &[u8]) -> [u8; 8] {
+    let mut out = [0; 8];
+    out.copy_from_slice(slice);
+    out
+}
+
+// Waits for a peer to join on the given port and sends it the settings
+pub fn host(port: u16, settings: &Settings) -> NetplayResult<Session> {
+    let listener = TcpListener::bind(("0.0.0.0", port))?;
+    println!(
+        "Waiting for a peer on port {}",
+        listener.local_addr()?.port()
+    );
+    accept(&listener, settings)
+}
+
+// Accepts a peer on a bound listener and sends it the settings
+pub fn accept(listener: &TcpListener, settings: &Settings) -> NetplayResult<Session> {
+    let (mut stream, addr) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    stream.write_all(&settings.encode())?;
+
+    let mut reply = [0; REPLY_LEN];
+    stream.read_exact(&mut reply)?;
+    if &reply[..4] != MAGIC {
+        return Err(NetplayError::Handshake(
+            "peer is not a chip-8 client".into(),
+        ));
+    }
+    let rom_hash = u32::from_be_bytes([reply[4], reply[5], reply[6], reply[7]]);
+    if rom_hash != settings.rom_hash {
+        return Err(NetplayError::Handshake(format!(
+            "peer runs ROM {:08x}, expected {:08x}",
+            rom_hash, settings.rom_hash
+        )));
+    }
+    println!("Peer joined from {}", addr);
+    Ok(Session::new(stream, settings.delay))
+}
+
+// Joins a host, returning the session and the settings to run with
+pub fn join<A: ToSocketAddrs>(addr: A, rom_hash: u32) -> NetplayResult<(Session, Settings)> {
+    let mut stream = TcpStream::connect(addr)?;
+    stream.set_nodelay(true)?;
+
+    let mut hello = [0; HELLO_LEN];
+    stream.read_exact(&mut hello)?;
+    let settings = Settings::decode(&hello)?;
+
+    // Reply even on a ROM mismatch, so the host reports it too
+    let mut reply = Vec::with_capacity(REPLY_LEN);
+    reply.extend_from_slice(MAGIC);
+    reply.extend_from_slice(&rom_hash.to_be_bytes());
+    stream.write_all(&reply)?;
+    if settings.rom_hash != rom_hash {
+        return Err(NetplayError::Handshake(format!(
+            "host runs ROM {:08x}, this ROM is {:08x}",
+            settings.rom_hash, rom_hash
+        )));
+    }
+    println!("Joined host with {} frames of input delay", settings.delay);
+    Ok((Session::new(stream, settings.delay), settings))
+}
+
+// A connected netplay session
+pub struct Session {
+    stream: TcpStream,
+    delay: u64,
+    frame: u64,                      // Next frame to run
+    local: VecDeque<u16>,            // Local keys of the upcoming frames
+    remote: VecDeque<u16>,           // Remote keys of the upcoming frames
+    remote_frames: u64,              // Frames with known remote keys
+    checksums: VecDeque<(u64, u32)>, // Local checksums not yet compared
+}
+
+impl Session {
+    fn new(stream: TcpStream, delay: u32) -> Self {
+        let delay = delay as u64;
+        // Nobody presses keys in the first `delay` frames
+        Session {
+            stream,
+            delay,
+            frame: 0,
+            local: (0..delay).map(|_| 0).collect(),
+            remote: (0..delay).map(|_| 0).collect(),
+            remote_frames: delay,
+            checksums: VecDeque::new(),
+        }
+    }
+
+    pub fn frame(&self) -> u64 {
+        self.frame
+    }
+
+    // Runs the next frame with the given local key mask (bit N for key N),
+    // waiting for the peer's keys if needed
+    pub fn advance(&mut self, chip8: &mut Chip8, keys: u16) -> NetplayResult<()> {
+        let checksum = state_checksum(chip8);
+        self.checksums.push_back((self.frame, checksum));
+
+        // Send the keys for `delay` frames ahead
+        let mut msg = [0; INPUT_LEN];
+        msg[..8].copy_from_slice(&(self.frame + self.delay).to_be_bytes());
+        msg[8..10].copy_from_slice(&keys.to_be_bytes());
+        msg[10..].copy_from_slice(&checksum.to_be_bytes());
+        if let Err(e) = self.stream.write_all(&msg) {
+            // A peer that already ran its last frame may have left. If its
+            // input is still needed, the read below reports it
+            match e.kind() {
+                io::ErrorKind::BrokenPipe
+                | io::ErrorKind::ConnectionReset
+                | io::ErrorKind::ConnectionAborted => (),
+                _ => return Err(e.into()),
+            }
+        }
+        self.local.push_back(keys);
+
+        // Wait for the remote keys of this frame
+        while self.remote_frames <= self.frame {
+            self.receive()?;
+        }
+
+        // The machine sees the keys of both peers
+        let keys = self.local.pop_front().unwrap() | self.remote.pop_front().unwrap();
+        chip8.run_frame(&mut MaskKeypad(keys))?;
+        self.frame += 1;
+        Ok(())
+    }
+
+    fn receive(&mut self) -> NetplayResult<()> {
+        let mut msg = [0; INPUT_LEN];
+        self.stream.read_exact(&mut msg)?;
+        let frame = u64::from_be_bytes(bytes(&msg[..8]));
+        if frame != self.remote_frames {
+            return Err(NetplayError::Io(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "expected input for frame {}, got {}",
+                    self.remote_frames, frame
+                ),
+            )));
+        }
+        self.remote.push_back(u16::from_be_bytes([msg[8], msg[9]]));
+        self.remote_frames += 1;
+
+        // The peer sent this at frame `frame - delay`, which ran here already
+        let sent_at = frame - self.delay;
+        let checksum = u32::from_be_bytes([msg[10], msg[11], msg[12], msg[13]]);
+        while let Some(&(f, local)) = self.checksums.front() {
+            if f > sent_at {
+                break;
+            }
+            self.checksums.pop_front();
+            if f == sent_at && local != checksum {
+                return Err(NetplayError::Desync(f));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Checksum of the machine state that affects emulation
+pub fn state_checksum(chip8: &Chip8) -> u32 {
+    let mut state = Vec::with_capacity(chip8.ram.len() + chip8.display.len() + 192);
+    state.extend_from_slice(&chip8.ram);
+    state.extend_from_slice(&chip8.registers);
+    state.extend_from_slice(&chip8.index.to_be_bytes());
+    state.extend_from_slice(&(chip8.pc as u16).to_be_bytes());
+    for addr in chip8.stack.iter() {
+        state.extend_from_slice(&addr.to_be_bytes());
+    }
+    state.push(chip8.istack as u8);
+    state.push(chip8.dt);
+    state.push(chip8.st);
+    state.extend_from_slice(&chip8.rng_state().to_be_bytes());
+    match chip8.status {
+        Status::Running => state.push(0),
+        Status::WaitingForKey { held } => {
+            state.push(1);
+            state.extend_from_slice(&held.to_be_bytes());
+        }
+    }
+    state.extend_from_slice(&chip8.display);
+    crc32(&state)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FONTS;
+    use std::{
+        sync::{Arc, Barrier},
+        thread,
+    };
+
+    // Paddle demo: key 1 moves a dot right, key C moves it down, so the
+    // final frame depends on the keys of both peers
+    const ROM: [u8; 22] = [
+        0xA2, 0x14, // I = sprite
+        0x00, 0xE0, // CLS
+        0x61, 0x01, // V1 = 1
+        0xE1, 0xA1, // SKNP V1
+        0x70, 0x01, // V0 += 1
+        0x61, 0x0C, // V1 = C
+        0xE1, 0xA1, // SKNP V1
+        0x72, 0x01, // V2 += 1
+        0xD0, 0x21, // DRW V0, V2, 1
+        0x12, 0x02, // JP 202
+        0x80, 0x00, // Sprite
+    ];
+
+    fn machine(settings: &Settings) -> Chip8 {
+        let mut chip8 = Chip8::new(ROM.to_vec(), FONTS, 0, settings.instruction_time_ns, false);
+        chip8.seed(settings.seed);
+        chip8.quirks = settings.quirks;
+        chip8
+    }
+
+    #[test]
+    fn peers_stay_in_sync() {
+        let settings = Settings {
+            rom_hash: crc32(&ROM),
+            seed: 7,
+            delay: 2,
+            instruction_time_ns: 1_000_000,
+            quirks: Quirks::default(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        let host = thread::spawn(move || {
+            let mut session = accept(&listener, &settings).unwrap();
+            let mut chip8 = machine(&settings);
+            for frame in 0..30 {
+                let keys = if frame < 10 { 1 << 0x1 } else { 0 };
+                session.advance(&mut chip8, keys).unwrap();
+            }
+            state_checksum(&chip8)
+        });
+
+        let (mut session, joined) = join(addr, crc32(&ROM)).unwrap();
+        assert_eq!(joined, settings);
+        let mut chip8 = machine(&joined);
+        for frame in 0..30 {
+            let keys = if frame >= 20 { 1 << 0xC } else { 0 };
+            session.advance(&mut chip8, keys).unwrap();
+        }
+
+        assert_eq!(host.join().unwrap(), state_checksum(&chip8));
+        // Both peers' keys reached the machine
+        assert!(chip8.registers[0] > 0);
+        assert!(chip8.registers[2] > 0);
+    }
+
+    #[test]
+    fn detects_desync() {
+        let settings = Settings {
+            rom_hash: crc32(&ROM),
+            seed: 7,
+            delay: 2,
+            instruction_time_ns: 1_000_000,
+            quirks: Quirks::default(),
+        };
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        // Neither peer hangs up before both saw the desync
+        let done = Arc::new(Barrier::new(2));
+
+        // Runs until the session fails, the host's RAM changes before frame 5
+        let run = |mut session: Session, mut chip8: Chip8, host: bool, done: Arc<Barrier>| {
+            let mut result = Ok(());
+            for frame in 0..30 {
+                if host && frame == 5 {
+                    chip8.ram[0xF00] = 1;
+                }
+                result = session.advance(&mut chip8, 0);
+                if result.is_err() {
+                    break;
+                }
+            }
+            done.wait();
+            result
+        };
+
+        let host_done = done.clone();
+        let host = thread::spawn(move || {
+            let session = accept(&listener, &settings).unwrap();
+            run(session, machine(&settings), true, host_done)
+        });
+        let (session, joined) = join(addr, crc32(&ROM)).unwrap();
+        let result = run(session, machine(&joined), false, done);
+
+        assert!(matches!(host.join().unwrap(), Err(NetplayError::Desync(5))));
+        assert!(matches!(result, Err(NetplayError::Desync(5))));
+    }
+
+    #[test]
+    fn checksum_covers_key_wait() {
+        let mut chip8 = Chip8::new(ROM.to_vec(), FONTS, 0, 1, false);
+        let running = state_checksum(&chip8);
+        chip8.status = Status::WaitingForKey { held: 0 };
+        let waiting = state_checksum(&chip8);
+        chip8.status = Status::WaitingForKey { held: 1 << 5 };
+        assert_ne!(running, waiting);
+        assert_ne!(waiting, state_checksum(&chip8));
+    }
+
+    #[test]
+    fn rejects_bad_settings() {
+        let settings = Settings {
+            rom_hash: 1,
+            seed: 7,
+            delay: 2,
+            instruction_time_ns: 1_000_000,
+            quirks: Quirks::default(),
+        };
+        let decode = |settings: Settings| {
+            let mut hello = [0; HELLO_LEN];
+            hello.copy_from_slice(&settings.encode());
+            Settings::decode(&hello)
+        };
+        assert_eq!(decode(settings).unwrap(), settings);
+        for bad in [
+            Settings {
+                delay: u32::MAX,
+                ..settings
+            },
+            Settings {
+                instruction_time_ns: 0,
+                ..settings
+            },
+        ] {
+            assert!(matches!(decode(bad), Err(NetplayError::Handshake(_))));
+        }
+
+        let mut hello = [0; HELLO_LEN];
+        hello.copy_from_slice(&settings.encode());
+        hello[31] = 2;
+        assert!(Settings::decode(&hello).is_err());
+    }
+
+    #[test]
+    fn checksum_covers_rng() {
+        let mut chip8 = Chip8::new(ROM.to_vec(), FONTS, 0, 1, false);
+        chip8.seed(1);
+        let before = state_checksum(&chip8);
+        chip8.seed(2);
+        assert_ne!(before, state_checksum(&chip8));
+    }
+}
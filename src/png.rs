@@ -0,0 +1,115 @@
+// Minimal PNG encoder for palette images, no compression library needed.
+// Image data is wrapped in zlib stored (uncompressed) deflate blocks.
+
+use crate::util::crc32;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+// Largest payload of a stored deflate block
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+// Encodes a 1 bit per pixel indexed PNG. `pixels` holds one byte per pixel,
+// zero picks `palette[0]` and anything else `palette[1]`
+pub fn encode_indexed(
+    width: usize,
+    height: usize,
+    pixels: &[u8],
+    palette: [(u8, u8, u8); 2],
+) -> Vec<u8> {
+    let mut png = SIGNATURE.to_vec();
+
+    // IHDR: size, bit depth 1, color type 3 (indexed)
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.extend_from_slice(&(width as u32).to_be_bytes());
+    ihdr.extend_from_slice(&(height as u32).to_be_bytes());
+    ihdr.extend_from_slice(&[1, 3, 0, 0, 0]);
+    write_chunk(&mut png, b"IHDR", &ihdr);
+
+    let plte: Vec<u8> = palette
+        .iter()
+        .flat_map(|&(r, g, b)| vec![r, g, b])
+        .collect();
+    write_chunk(&mut png, b"PLTE", &plte);
+
+    // Scanlines are prefixed with filter type 0 and packed MSB first
+    let row_len = width.div_ceil(8);
+    let mut raw = Vec::with_capacity((row_len + 1) * height);
+    for row in pixels.chunks(width).take(height) {
+        raw.push(0);
+        let start = raw.len();
+        raw.resize(start + row_len, 0);
+        for (x, &p) in row.iter().enumerate() {
+            if p > 0 {
+                raw[start + x / 8] |= 0x80 >> (x % 8);
+            }
+        }
+    }
+    write_chunk(&mut png, b"IDAT", &zlib_stored(&raw));
+    write_chunk(&mut png, b"IEND", &[]);
+    png
+}
+
+fn write_chunk(png: &mut Vec<u8>, kind: &[u8; 4], data: &[u8]) {
+    png.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    let start = png.len();
+    png.extend_from_slice(kind);
+    png.extend_from_slice(data);
+    let crc = crc32(&png[start..]);
+    png.extend_from_slice(&crc.to_be_bytes());
+}
+
+// Wraps data in a zlib stream made of stored deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    // CMF: deflate with 32K window, FLG: no dictionary, check bits
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        // An empty stream still needs one final block
+        out.extend_from_slice(&[1, 0, 0, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let (mut a, mut b) = (1_u32, 0_u32);
+    for &byte in data {
+        a = (a + byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn checksums() {
+        assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+        assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+    }
+
+    #[test]
+    fn encodes_1bit_rows() {
+        let png = encode_indexed(
+            9,
+            1,
+            &[1, 0, 0, 0, 0, 0, 0, 0, 1],
+            [(0, 0, 0), (255, 255, 255)],
+        );
+        assert_eq!(&png[..8], &SIGNATURE);
+        // Filter byte followed by the packed row, inside a single stored block
+        let raw = [0, 0x80, 0x80];
+        let idat = zlib_stored(&raw);
+        assert!(png.windows(idat.len()).any(|w| w == idat.as_slice()));
+        assert_eq!(&idat[2..7], &[1, 3, 0, 0xFC, 0xFF]);
+    }
+}
@@ -0,0 +1,482 @@
+use std::{
+    fs,
+    io::{self, BufRead, BufReader, Write},
+    net::TcpListener,
+    sync::mpsc::{self, Receiver, Sender, TryRecvError},
+    thread,
+};
+
+use serde_json::{json, Map, Value};
+
+use crate::{
+    emulator::{Chip8, Quirks, Status},
+    headless::{self, DumpFormat},
+    keyboard::MaskKeypad,
+    DISPLAY_HEIGHT, DISPLAY_LEN, DISPLAY_WIDTH, FONTS, MAX_ROM_SIZE, NUM_REGISTERS, RAM_SIZE,
+    STACK_SIZE,
+};
+
+// Remote control: other processes drive the machine with one JSON object
+// per line, e.g. {"cmd": "run_frames", "count": 60}, over stdin/stdout or a
+// local TCP socket. Each command gets a one line reply with "ok" set, and
+// "error" on failure. An "id" in the command is echoed in the reply.
+//
+// The machine only runs when told to, with the keys held by press/release.
+
+// Settings machines are created with on load and reset
+pub struct RemoteSession {
+    pub rom: Vec<u8>,
+    pub quirks: Quirks,
+    pub seed: u64,
+    pub keypad: MaskKeypad,
+}
+
+impl RemoteSession {
+    pub fn new(rom: Vec<u8>, quirks: Quirks, seed: u64) -> Self {
+        RemoteSession {
+            rom,
+            quirks,
+            seed,
+            keypad: MaskKeypad::default(),
+        }
+    }
+
+    // Restarts the machine with the ROM, keeping its backend, symbols,
+    // hooks and cheats
+    fn reset(&self, chip8: &mut Chip8) {
+        chip8.reset(self.rom.clone(), FONTS);
+        chip8.quirks = self.quirks;
+        chip8.seed(self.seed);
+    }
+
+    // Runs a command line and returns the reply line
+    pub fn handle(&mut self, chip8: &mut Chip8, line: &str) -> String {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return error_reply(None, format!("Invalid JSON: {}", e)),
+        };
+        let id = request.get("id").cloned();
+        match self.execute(chip8, &request) {
+            Ok(mut reply) => {
+                reply.insert("ok".into(), Value::Bool(true));
+                if let Some(id) = id {
+                    reply.insert("id".into(), id);
+                }
+                Value::Object(reply).to_string()
+            }
+            Err(e) => error_reply(id, e),
+        }
+    }
+
+    fn execute(&mut self, chip8: &mut Chip8, req: &Value) -> Result<Map<String, Value>, String> {
+        let cmd = req
+            .get("cmd")
+            .and_then(Value::as_str)
+            .ok_or("Missing \"cmd\"")?;
+        let reply = match cmd {
+            // {"cmd": "load", "path": "game.ch8"} or {"cmd": "load", "rom": [bytes]}
+            "load" => {
+                let rom = match (req.get("path"), req.get("rom")) {
+                    (Some(path), _) => {
+                        let path = path.as_str().ok_or("\"path\" must be a string")?;
+                        fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?
+                    }
+                    (None, Some(rom)) => bytes(rom, "rom")?,
+                    (None, None) => return Err("Expected \"path\" or \"rom\"".into()),
+                };
+                if rom.len() > MAX_ROM_SIZE {
+                    return Err(format!("The ROM is larger than {} bytes", MAX_ROM_SIZE));
+                }
+                self.rom = rom;
+                self.reset(chip8);
+                json!({ "size": self.rom.len() })
+            }
+            "reset" => {
+                self.reset(chip8);
+                json!({})
+            }
+            // {"cmd": "step", "count": 1}
+            "step" => {
+                for _ in 0..opt_u64(req, "count", 1)? {
+                    chip8.step(&mut self.keypad).map_err(|e| e.to_string())?;
+                }
+                json!({ "pc": chip8.pc })
+            }
+            // {"cmd": "run_frames", "count": 1}
+            "run_frames" => {
+                for _ in 0..opt_u64(req, "count", 1)? {
+                    chip8
+                        .run_frame(&mut self.keypad)
+                        .map_err(|e| e.to_string())?;
+                }
+                json!({ "pc": chip8.pc })
+            }
+            // {"cmd": "press", "key": 5}
+            "press" => {
+                self.keypad.0 |= 1 << key(req)?;
+                json!({ "keys": self.keypad.0 })
+            }
+            "release" => {
+                self.keypad.0 &= !(1 << key(req)?);
+                json!({ "keys": self.keypad.0 })
+            }
+            // {"cmd": "read_mem", "addr": 512, "len": 16}
+            "read_mem" => {
+                let addr = address(req)?;
+                let len = opt_u64(req, "len", 1)? as usize;
+                if len > RAM_SIZE - addr {
+                    return Err(format!("Read past the end of memory ({} bytes)", RAM_SIZE));
+                }
+                json!({ "addr": addr, "data": &chip8.ram[addr..addr + len] })
+            }
+            // {"cmd": "write_mem", "addr": 512, "data": [1, 2, 3]}
+            "write_mem" => {
+                let addr = address(req)?;
+                let data = bytes(req.get("data").ok_or("Missing \"data\"")?, "data")?;
+                if data.len() > RAM_SIZE - addr {
+                    return Err(format!("Write past the end of memory ({} bytes)", RAM_SIZE));
+                }
+                chip8.write_ram(addr, &data);
+                json!({ "addr": addr, "len": data.len() })
+            }
+            "get_regs" => json!({
+                "v": chip8.registers,
+                "i": chip8.index,
+                "pc": chip8.pc,
+                "sp": chip8.istack,
+                "stack": &chip8.stack[1..=chip8.istack],
+                "dt": chip8.dt,
+                "st": chip8.st,
+                "waiting_for_key": chip8.status != Status::Running,
+            }),
+            // Rows of '#' (on) and '.' (off)
+            "get_screen" => {
+                let dump = headless::dump(
+                    &chip8.display,
+                    DISPLAY_WIDTH,
+                    DISPLAY_HEIGHT,
+                    DumpFormat::Ascii,
+                );
+                json!({
+                    "width": DISPLAY_WIDTH,
+                    "height": DISPLAY_HEIGHT,
+                    "rows": dump.lines().collect::<Vec<_>>(),
+                })
+            }
+            // {"cmd": "save_state", "path": "state.json"}, the state is
+            // returned if no path is given
+            "save_state" => {
+                let state = save_state(chip8);
+                match req.get("path").and_then(Value::as_str) {
+                    Some(path) => {
+                        fs::write(path, state.to_string())
+                            .map_err(|e| format!("Could not write {}: {}", path, e))?;
+                        json!({ "path": path })
+                    }
+                    None => json!({ "state": state }),
+                }
+            }
+            // {"cmd": "load_state", "path": "state.json"} or {"cmd":
+            // "load_state", "state": {...}}
+            "load_state" => {
+                let state = match (req.get("path"), req.get("state")) {
+                    (Some(path), _) => {
+                        let path = path.as_str().ok_or("\"path\" must be a string")?;
+                        let text = fs::read_to_string(path)
+                            .map_err(|e| format!("Could not read {}: {}", path, e))?;
+                        serde_json::from_str(&text)
+                            .map_err(|e| format!("Invalid state in {}: {}", path, e))?
+                    }
+                    (None, Some(state)) => state.clone(),
+                    (None, None) => return Err("Expected \"path\" or \"state\"".into()),
+                };
+                load_state(chip8, &state)?;
+                json!({})
+            }
+            _ => return Err(format!("Unknown command '{}'", cmd)),
+        };
+        match reply {
+            Value::Object(map) => Ok(map),
+            _ => unreachable!(),
+        }
+    }
+}
+
+fn error_reply(id: Option<Value>, error: String) -> String {
+    let mut reply = json!({ "ok": false, "error": error });
+    if let Some(id) = id {
+        reply["id"] = id;
+    }
+    reply.to_string()
+}
+
+fn opt_u64(req: &Value, name: &str, default: u64) -> Result<u64, String> {
+    match req.get(name) {
+        Some(value) => value
+            .as_u64()
+            .ok_or_else(|| format!("\"{}\" must be an unsigned integer", name)),
+        None => Ok(default),
+    }
+}
+
+fn address(req: &Value) -> Result<usize, String> {
+    let addr = req
+        .get("addr")
+        .and_then(Value::as_u64)
+        .ok_or("Missing \"addr\"")? as usize;
+    if addr >= RAM_SIZE {
+        return Err(format!("Address {} is outside of memory", addr));
+    }
+    Ok(addr)
+}
+
+// Key value as a number or hex digit string
+fn key(req: &Value) -> Result<u8, String> {
+    let key = match req.get("key") {
+        Some(Value::Number(n)) => n.as_u64(),
+        Some(Value::String(s)) => u64::from_str_radix(s, 16).ok(),
+        _ => None,
+    };
+    match key {
+        Some(k) if k < 16 => Ok(k as u8),
+        _ => Err("\"key\" must be a key value from 0 to F".into()),
+    }
+}
+
+fn bytes(value: &Value, name: &str) -> Result<Vec<u8>, String> {
+    let err = || format!("\"{}\" must be an array of bytes", name);
+    value
+        .as_array()
+        .ok_or_else(err)?
+        .iter()
+        .map(|b| b.as_u64().filter(|&b| b < 256).map(|b| b as u8))
+        .collect::<Option<Vec<u8>>>()
+        .ok_or_else(err)
+}
+
+// Machine state as JSON
+pub fn save_state(chip8: &Chip8) -> Value {
+    json!({
+        "ram": &chip8.ram[..],
+        "v": chip8.registers,
+        "i": chip8.index,
+        "pc": chip8.pc,
+        "stack": &chip8.stack[..],
+        "sp": chip8.istack,
+        "dt": chip8.dt,
+        "st": chip8.st,
+        "display": &chip8.display[..],
+        "key_wait": match chip8.status {
+            Status::Running => Value::Null,
+            Status::WaitingForKey { held } => json!({ "held": held }),
+        },
+    })
+}
+
+// Restores a state made by save_state
+pub fn load_state(chip8: &mut Chip8, state: &Value) -> Result<(), String> {
+    let ram = bytes(&state["ram"], "ram")?;
+    let registers = bytes(&state["v"], "v")?;
+    let display = bytes(&state["display"], "display")?;
+    let stack: Option<Vec<u16>> = state["stack"].as_array().and_then(|s| {
+        s.iter()
+            .map(|a| a.as_u64().filter(|&a| a <= 0xFFFF).map(|a| a as u16))
+            .collect()
+    });
+    let field = |name: &str, max: u64| {
+        state[name]
+            .as_u64()
+            .filter(|&n| n <= max)
+            .ok_or_else(|| format!("Invalid \"{}\" in state", name))
+    };
+    let (index, pc, sp) = (
+        field("i", 0xFFFF)?,
+        field("pc", RAM_SIZE as u64)?,
+        field("sp", STACK_SIZE as u64 - 1)?,
+    );
+    let (dt, st) = (field("dt", 0xFF)?, field("st", 0xFF)?);
+    match stack {
+        Some(stack)
+            if ram.len() == RAM_SIZE
+                && registers.len() == NUM_REGISTERS
+                && display.len() == DISPLAY_LEN
+                && stack.len() == STACK_SIZE =>
+        {
+            chip8.write_ram(0, &ram);
+            chip8.registers.copy_from_slice(&registers);
+            chip8.display.copy_from_slice(&display);
+            chip8.stack.copy_from_slice(&stack);
+        }
+        _ => return Err("State does not match this machine".into()),
+    }
+    chip8.index = index as u16;
+    chip8.pc = pc as usize;
+    chip8.istack = sp as usize;
+    chip8.dt = dt as u8;
+    chip8.st = st as u8;
+    // States from before FX0A waits were saved have none
+    chip8.status = match state["key_wait"]["held"].as_u64() {
+        Some(held) if held <= 0xFFFF => Status::WaitingForKey { held: held as u16 },
+        _ => Status::Running,
+    };
+    chip8.display_update_flag = true;
+    Ok(())
+}
+
+// A connected remote client
+pub struct Remote {
+    pub session: RemoteSession,
+    lines: Receiver<String>,
+    out: Box<dyn Write + Send>,
+}
+
+impl Remote {
+    // Opens the remote control channel: "stdio" for stdin/stdout, else a
+    // local port to wait for one client on
+    pub fn open(target: &str, session: RemoteSession) -> io::Result<Self> {
+        let (tx, lines) = mpsc::channel();
+        let out: Box<dyn Write + Send> = if target == "stdio" {
+            forward_lines(BufReader::new(io::stdin()), tx);
+            Box::new(io::stdout())
+        } else {
+            let port = target.parse::<u16>().map_err(|e| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("The remote port ({}) is not a valid port: {}", target, e),
+                )
+            })?;
+            let listener = TcpListener::bind(("127.0.0.1", port))?;
+            println!("Waiting for a remote client on port {}", port);
+            let (stream, addr) = listener.accept()?;
+            println!("Remote client connected from {}", addr);
+            forward_lines(BufReader::new(stream.try_clone()?), tx);
+            Box::new(stream)
+        };
+        Ok(Remote {
+            session,
+            lines,
+            out,
+        })
+    }
+
+    // Runs pending commands, waiting for one if `block` is set. Returns
+    // false once the client is gone or sent {"cmd": "quit"}
+    pub fn poll(&mut self, chip8: &mut Chip8, block: bool) -> io::Result<bool> {
+        loop {
+            let line = if block {
+                match self.lines.recv() {
+                    Ok(line) => line,
+                    Err(_) => return Ok(false),
+                }
+            } else {
+                match self.lines.try_recv() {
+                    Ok(line) => line,
+                    Err(TryRecvError::Empty) => return Ok(true),
+                    Err(TryRecvError::Disconnected) => return Ok(false),
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let quit = serde_json::from_str::<Value>(&line)
+                .map(|req| req["cmd"] == "quit")
+                .unwrap_or(false);
+            let reply = if quit {
+                json!({ "ok": true }).to_string()
+            } else {
+                self.session.handle(chip8, &line)
+            };
+            writeln!(self.out, "{}", reply)?;
+            self.out.flush()?;
+            if quit {
+                return Ok(false);
+            }
+        }
+    }
+}
+
+// Sends the lines read to the channel from a thread, until end of input
+fn forward_lines<R: BufRead + Send + 'static>(reader: R, tx: Sender<String>) {
+    thread::spawn(move || {
+        for line in reader.lines() {
+            match line {
+                Ok(line) => {
+                    if tx.send(line).is_err() {
+                        break;
+                    }
+                }
+                Err(_) => break,
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod test {
+    use std::{cell::Cell, rc::Rc};
+
+    use super::*;
+    use crate::emulator::Backend;
+
+    #[test]
+    fn runs_commands() {
+        // V0 = 7, store it at 0x300, then wait for a key into V1
+        let rom = vec![0xA3, 0x00, 0x60, 0x07, 0xF0, 0x55, 0xF1, 0x0A];
+        let mut session = RemoteSession::new(rom.clone(), Quirks::default(), 0);
+        let mut chip8 = Chip8::new(rom, FONTS, 0, 1, false);
+
+        let mut run = |line: &str| -> Value {
+            serde_json::from_str(&session.handle(&mut chip8, line)).unwrap()
+        };
+        assert_eq!(run(r#"{"cmd": "step", "count": 4, "id": 1}"#)["id"], 1);
+        let mem = run(r#"{"cmd": "read_mem", "addr": 768, "len": 2}"#);
+        assert_eq!(mem["data"], json!([7, 0]));
+        // FX0A takes the key when it is released
+        run(r#"{"cmd": "press", "key": "b"}"#);
+        run(r#"{"cmd": "step"}"#);
+        assert_eq!(run(r#"{"cmd": "get_regs"}"#)["waiting_for_key"], true);
+        run(r#"{"cmd": "release", "key": "b"}"#);
+        run(r#"{"cmd": "step"}"#);
+        let regs = run(r#"{"cmd": "get_regs"}"#);
+        assert_eq!(regs["v"][1], 0xB);
+        assert_eq!(regs["waiting_for_key"], false);
+
+        let state = run(r#"{"cmd": "save_state"}"#)["state"].clone();
+        run(r#"{"cmd": "reset"}"#);
+        assert_eq!(run(r#"{"cmd": "get_regs"}"#)["pc"], 0x200);
+        let load = json!({ "cmd": "load_state", "state": state }).to_string();
+        assert_eq!(run(&load)["ok"], true);
+        assert_eq!(run(r#"{"cmd": "get_regs"}"#)["pc"], 0x208);
+
+        // A rejected ROM does not replace the loaded one
+        let big = json!({ "cmd": "load", "rom": vec![0; MAX_ROM_SIZE + 1] }).to_string();
+        assert_eq!(run(&big)["ok"], false);
+        run(r#"{"cmd": "reset"}"#);
+        let mem = run(r#"{"cmd": "read_mem", "addr": 512, "len": 2}"#);
+        assert_eq!(mem["data"], json!([0xA3, 0x00]));
+
+        let err = run(r#"{"cmd": "read_mem", "addr": 5000}"#);
+        assert_eq!(err["ok"], false);
+        let err = run(r#"{"cmd": "read_mem", "addr": 1, "len": 18446744073709551615}"#);
+        assert_eq!(err["ok"], false);
+    }
+
+    #[test]
+    fn reset_keeps_settings() {
+        let rom = vec![0x60, 0x07, 0x12, 0x00];
+        let mut session = RemoteSession::new(rom.clone(), Quirks::default(), 0);
+        let mut chip8 = Chip8::new(rom, FONTS, 0, 1, false);
+        chip8.backend = Backend::Threaded;
+        let traced = Rc::new(Cell::new(0));
+        let count = traced.clone();
+        chip8.on_before_instruction(move |_, _| count.set(count.get() + 1));
+
+        for line in [r#"{"cmd": "reset"}"#, r#"{"cmd": "load", "rom": [96, 1]}"#] {
+            traced.set(0);
+            session.handle(&mut chip8, line);
+            session.handle(&mut chip8, r#"{"cmd": "step"}"#);
+            assert_eq!(chip8.backend, Backend::Threaded);
+            assert_eq!(traced.get(), 1);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+use std::{fs, io, path::Path};
+
+use crate::{png, time};
+
+// Writes the framebuffer to a PNG, each pixel blown up to `scale` x `scale`
+pub fn save_png(
+    path: &Path,
+    buffer: &[u8],
+    width: usize,
+    height: usize,
+    scale: u32,
+    fg_col: (u8, u8, u8),
+    bg_col: (u8, u8, u8),
+) -> io::Result<()> {
+    let scale = scale.max(1) as usize;
+    let mut pixels = Vec::with_capacity(width * height * scale * scale);
+    for row in buffer.chunks(width).take(height) {
+        let start = pixels.len();
+        for &p in row {
+            pixels.extend(std::iter::repeat_n(p, scale));
+        }
+        for _ in 1..scale {
+            pixels.extend_from_within(start..start + width * scale);
+        }
+    }
+    let data = png::encode_indexed(width * scale, height * scale, &pixels, [bg_col, fg_col]);
+    fs::write(path, data)
+}
+
+// Capture file name from the ROM name, the current time and a suffix, e.g.
+// "Cave-20211004-153012-10x.png" for the suffix "-10x.png"
+pub fn filename(rom: &str, suffix: &str) -> String {
+    let name = Path::new(rom)
+        .file_stem()
+        .map(|s| s.to_string_lossy().into_owned())
+        .unwrap_or_else(|| String::from("chip-8"));
+    format!("{}-{}{}", name, time::timestamp(), suffix)
+}
@@ -0,0 +1,290 @@
+use std::{error::Error, path::Path};
+
+use sdl2::{
+    event::{Event, WindowEvent},
+    keyboard::{Keycode, Mod},
+};
+
+use crate::{
+    audio::Beep,
+    cheat::CheatConsole,
+    display::{Display, Persistence},
+    emulator::Chip8,
+    filter::Filters,
+    gif::GifRecorder,
+    keyboard::key_mask,
+    netplay::Session,
+    remote::Remote,
+    screenshot, time,
+    tone::Tone,
+    viewport::{Aspect, Scaling},
+    DISPLAY_HEIGHT, DISPLAY_WIDTH, FRAME_TIME_NS,
+};
+
+// Settings of the SDL frontend
+pub struct Options<'a> {
+    pub rom: &'a str,
+    pub scale: u32,
+    pub fgcol: (u8, u8, u8),
+    pub bgcol: (u8, u8, u8),
+    pub persistence: Persistence,
+    pub filters: Filters,
+    pub scaling: Scaling,
+    pub aspect: Aspect,
+    pub fullscreen: bool,
+    pub tone: Tone,
+    pub muted: bool,
+    pub record_gif: Option<&'a str>,
+    pub rom_hash: u32,
+    pub cheat_file: Option<&'a str>,
+}
+
+// What advances the machine
+pub enum Driver<'a> {
+    // Runs in real time at the configured speed
+    Clock,
+    // Runs whole frames in lockstep with a netplay peer
+    Netplay(&'a mut Session),
+    // Runs only when the remote client tells it to
+    Remote(&'a mut Remote),
+}
+
+// Runs the machine in an SDL window until it is closed
+pub fn run(chip8: &mut Chip8, options: &Options, mut driver: Driver) -> Result<(), Box<dyn Error>> {
+    let Options {
+        rom: filename,
+        scale,
+        fgcol,
+        bgcol,
+        ..
+    } = *options;
+
+    // Init SDL2
+    let sdl_context = sdl2::init().unwrap();
+
+    // Create the display
+    let mut display = Display::new(&sdl_context, "R-CHIP-8", scale, fgcol, bgcol);
+    display.persistence = options.persistence;
+    display.filters = options.filters;
+    display.scaling = options.scaling;
+    display.aspect = options.aspect;
+    if options.fullscreen {
+        display.set_fullscreen(true);
+    }
+
+    // Create audio beep
+    let mut beep = Beep::new(&sdl_context, options.tone.clone());
+    beep.set_muted(options.muted);
+
+    // GIF recording
+    let mut recorder = options
+        .record_gif
+        .and_then(|path| start_recording(Path::new(path), scale, fgcol, bgcol));
+
+    // Cheat console, keeps its RAM search between sessions
+    let mut cheat_console =
+        CheatConsole::new(options.rom_hash, options.cheat_file.map(String::from));
+
+    // Last frame time, the display is presented at most once per frame
+    let mut last_frame_t: u128 = time::time_nanos();
+    let mut frame_dirty = true;
+
+    // Main loop
+    'mainloop: loop {
+        let t: u128 = time::time_nanos();
+
+        // Event loop
+        let mut toggle_fullscreen = false;
+        for event in display.event_pump.poll_iter() {
+            match event {
+                Event::Quit { .. }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::CapsLock),
+                    ..
+                } => break 'mainloop,
+                // The frame is placed again in the new window size
+                Event::Window {
+                    win_event: WindowEvent::SizeChanged(..),
+                    ..
+                } => frame_dirty = true,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Return),
+                    keymod,
+                    repeat: false,
+                    ..
+                } if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => toggle_fullscreen = true,
+                Event::KeyDown {
+                    keycode: Some(keycode),
+                    repeat: false,
+                    ..
+                } => {
+                    // Filter, fullscreen and mute hotkeys
+                    let filters = &mut display.filters;
+                    match keycode {
+                        Keycode::F1 => filters.scanlines = !filters.scanlines,
+                        Keycode::F2 => filters.grid = !filters.grid,
+                        Keycode::F3 => filters.upscale = filters.upscale.next(),
+                        Keycode::F4 => filters.bloom = !filters.bloom,
+                        Keycode::F11 => toggle_fullscreen = true,
+                        Keycode::F8 => {
+                            beep.set_muted(!beep.is_muted());
+                            println!("Sound {}", if beep.is_muted() { "muted" } else { "on" });
+                            continue;
+                        }
+                        Keycode::F7 => {
+                            if let Driver::Netplay(_) = driver {
+                                // Cheats would desync the peers
+                                println!("The cheat console is not available in netplay");
+                                continue;
+                            }
+                            // Blocks the window until the console is left
+                            cheat_console.run(chip8);
+                            println!("Resumed");
+                        }
+                        Keycode::F9 => {
+                            match recorder.take() {
+                                Some(rec) => stop_recording(rec),
+                                None => {
+                                    let name = screenshot::filename(filename, ".gif");
+                                    recorder =
+                                        start_recording(Path::new(&name), scale, fgcol, bgcol);
+                                }
+                            }
+                            continue;
+                        }
+                        Keycode::F12 => {
+                            take_screenshot(filename, &chip8.display, display.scale, fgcol, bgcol);
+                            continue;
+                        }
+                        _ => continue,
+                    }
+                    frame_dirty = true;
+                }
+                _ => {}
+            }
+        }
+
+        if toggle_fullscreen {
+            display.set_fullscreen(!display.is_fullscreen());
+            frame_dirty = true;
+        }
+
+        // Run the machine
+        let result: Result<(), Box<dyn Error>> = match &mut driver {
+            Driver::Clock => chip8
+                .cycle(t, &mut display.event_pump)
+                .map_err(|e| e.into()),
+            Driver::Netplay(session) if t - last_frame_t > FRAME_TIME_NS => {
+                let keys = key_mask(&mut display.event_pump);
+                session.advance(chip8, keys).map_err(|e| e.into())
+            }
+            Driver::Netplay(_) => Ok(()),
+            Driver::Remote(remote) => match remote.poll(chip8, false) {
+                Ok(true) => {
+                    frame_dirty = true;
+                    Ok(())
+                }
+                Ok(false) => break 'mainloop,
+                Err(e) => Err(e.into()),
+            },
+        };
+        if let Err(e) = result {
+            if let Some(rec) = recorder {
+                stop_recording(rec);
+            }
+            return Err(e);
+        }
+
+        // Present the display once per frame if it changed
+        if chip8.display_clear_flag || chip8.display_update_flag {
+            frame_dirty = true;
+        }
+        if t - last_frame_t > FRAME_TIME_NS {
+            if frame_dirty || display.is_fading() {
+                display.render(&chip8.display);
+                frame_dirty = false;
+            }
+            if let Some(rec) = recorder.as_mut() {
+                if let Err(e) = rec.capture(&chip8.display) {
+                    println!("Stopped GIF recording: {}", e);
+                    recorder = None;
+                }
+            }
+            last_frame_t = t;
+        }
+
+        // Start/stop the beep
+        beep.set_playing(chip8.beep_flag);
+    }
+
+    if let Some(rec) = recorder {
+        stop_recording(rec);
+    }
+    Ok(())
+}
+
+// Saves the framebuffer at native resolution and at the display scale
+fn take_screenshot(
+    rom: &str,
+    buffer: &[u8],
+    scale: u32,
+    fg_col: (u8, u8, u8),
+    bg_col: (u8, u8, u8),
+) {
+    for &scl in &[1, scale] {
+        let name = screenshot::filename(rom, &format!("-{}x.png", scl));
+        match screenshot::save_png(
+            Path::new(&name),
+            buffer,
+            DISPLAY_WIDTH,
+            DISPLAY_HEIGHT,
+            scl,
+            fg_col,
+            bg_col,
+        ) {
+            Ok(()) => println!("Saved screenshot: {}", name),
+            Err(e) => println!("Could not save screenshot {}: {}", name, e),
+        }
+        if scale == 1 {
+            break;
+        }
+    }
+}
+
+// Starts recording a GIF at the display scale, one frame per 60 Hz tick
+fn start_recording(
+    path: &Path,
+    scale: u32,
+    fg_col: (u8, u8, u8),
+    bg_col: (u8, u8, u8),
+) -> Option<GifRecorder> {
+    match GifRecorder::create(
+        path,
+        DISPLAY_WIDTH,
+        DISPLAY_HEIGHT,
+        scale,
+        60,
+        fg_col,
+        bg_col,
+    ) {
+        Ok(rec) => {
+            println!("Recording GIF: {}", path.display());
+            Some(rec)
+        }
+        Err(e) => {
+            println!("Could not record GIF {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+fn stop_recording(recorder: GifRecorder) {
+    match recorder.finish() {
+        Ok(()) => println!("Stopped GIF recording"),
+        Err(e) => println!("Could not finish GIF recording: {}", e),
+    }
+}
@@ -0,0 +1,136 @@
+use std::{collections::BTreeMap, fs};
+
+use crate::{
+    emulator::{Decoded, Op},
+    RAM_SIZE,
+};
+
+// Symbols of a ROM from its assembler, so tools can show labels and
+// source lines instead of raw addresses. Symbol files hold labels and a
+// map from addresses to the source lines they were assembled from, like
+// the one Octo's debugger keeps. Addresses are hex:
+//
+//   # labels
+//   main = 200
+//   draw_player = 2a4
+//   # source lines, each holding up to the next mapped address
+//   200 : game.8o:12
+//   2a4 : player.8o:42
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Symbols {
+    pub labels: BTreeMap<usize, String>, // Label at each address
+    pub lines: BTreeMap<usize, String>,  // Source line starting at each address
+}
+
+impl Symbols {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("Could not read symbol file {}: {}", path, e))?;
+        Symbols::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, String> {
+        let mut symbols = Symbols::default();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.split('#').next().unwrap().trim();
+            if line.is_empty() {
+                continue;
+            }
+            if let Some((label, addr)) = line.split_once('=') {
+                let addr = parse_addr(addr).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+                symbols.labels.insert(addr, label.trim().to_string());
+            } else if let Some((addr, source)) = line.split_once(':') {
+                let addr = parse_addr(addr).map_err(|e| format!("Line {}: {}", n + 1, e))?;
+                symbols.lines.insert(addr, source.trim().to_string());
+            } else {
+                return Err(format!(
+                    "Line {}: expected 'label = address' or 'address : file:line'",
+                    n + 1
+                ));
+            }
+        }
+        Ok(symbols)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.labels.is_empty() && self.lines.is_empty()
+    }
+
+    // Nearest label at or before `addr`, as "draw_player+0x4"
+    pub fn label(&self, addr: usize) -> Option<String> {
+        let (start, name) = self.labels.range(..=addr).next_back()?;
+        Some(match addr - start {
+            0 => name.clone(),
+            offset => format!("{}+0x{:x}", name, offset),
+        })
+    }
+
+    // Source line `addr` was assembled from
+    pub fn source(&self, addr: usize) -> Option<&str> {
+        self.lines
+            .range(..=addr)
+            .next_back()
+            .map(|(_, line)| line.as_str())
+    }
+
+    // Label and source line of `addr`, empty without symbols
+    pub fn describe(&self, addr: usize) -> String {
+        match (self.label(addr), self.source(addr)) {
+            (Some(label), Some(source)) => format!("{} ({})", label, source),
+            (Some(label), None) => label,
+            (None, Some(source)) => format!("({})", source),
+            (None, None) => String::new(),
+        }
+    }
+
+    // Disassembly with addresses in operands replaced by labels
+    pub fn disassemble(&self, decoded: &Decoded) -> String {
+        let text = decoded.to_string();
+        let addr = decoded.nnn as usize;
+        match (decoded.op, self.label(addr)) {
+            (Op::Jp, Some(label))
+            | (Op::Call, Some(label))
+            | (Op::LdI, Some(label))
+            | (Op::JpV0, Some(label)) => {
+                let operand = format!("0x{:03X}", addr);
+                match text.strip_suffix(&operand) {
+                    Some(rest) => format!("{}{}", rest, label),
+                    None => text,
+                }
+            }
+            _ => text,
+        }
+    }
+}
+
+fn parse_addr(s: &str) -> Result<usize, String> {
+    let s = s.trim();
+    match usize::from_str_radix(s.trim_start_matches("0x"), 16) {
+        Ok(addr) if addr < RAM_SIZE => Ok(addr),
+        _ => Err(format!("Invalid address '{}'", s)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::emulator::decode;
+
+    #[test]
+    fn labels_and_lines() {
+        let symbols = Symbols::parse(
+            "main = 200\ndraw_player = 0x2a4 # sprite\n200 : game.8o:12\n2a4 : player.8o:42\n",
+        )
+        .unwrap();
+
+        assert_eq!(symbols.label(0x1ff), None);
+        assert_eq!(symbols.label(0x2a4).as_deref(), Some("draw_player"));
+        assert_eq!(symbols.label(0x2a8).as_deref(), Some("draw_player+0x4"));
+        assert_eq!(symbols.source(0x2a2), Some("game.8o:12"));
+        assert_eq!(symbols.describe(0x2a8), "draw_player+0x4 (player.8o:42)");
+        assert_eq!(symbols.disassemble(&decode(0x22A4)), "CALL draw_player");
+        assert_eq!(symbols.disassemble(&decode(0x6104)), "LD V1, 0x04");
+        assert!(Symbols::parse("main 200").is_err());
+    }
+}
@@ -0,0 +1,344 @@
+use std::fs;
+
+use crate::{DEF_ENVELOPE_MS, DEF_TONE_FREQ, DEF_VOLUME};
+
+// Sound of the beep played while the sound timer runs. The samples are
+// generated here, away from the audio device, so the frontend only has to
+// feed them to it.
+
+// Shape of one period of the beep
+#[derive(Clone, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Sine,
+    Triangle,
+    // Random levels, changed twice per period
+    Noise,
+    // A recorded sound, looped at its own rate. The frequency is ignored
+    Sample(Sample),
+}
+
+impl Waveform {
+    // A waveform name, or the path of a WAV file to use as sample
+    pub fn parse(waveform: &str) -> Result<Self, String> {
+        match waveform {
+            "square" => Ok(Waveform::Square),
+            "sine" => Ok(Waveform::Sine),
+            "triangle" => Ok(Waveform::Triangle),
+            "noise" => Ok(Waveform::Noise),
+            path if path.to_lowercase().ends_with(".wav") => {
+                Sample::load(path).map(Waveform::Sample)
+            }
+            _ => Err(format!(
+                "Unknown waveform '{}', expected square, sine, triangle, noise or a .wav file",
+                waveform
+            )),
+        }
+    }
+}
+
+// Mono sound read from a WAV file
+#[derive(Clone, Debug, PartialEq)]
+pub struct Sample {
+    pub rate: u32,      // Samples per second
+    pub data: Vec<f32>, // Samples between -1 and 1
+}
+
+impl Sample {
+    pub fn load(path: &str) -> Result<Self, String> {
+        let bytes = fs::read(path).map_err(|e| format!("Could not read {}: {}", path, e))?;
+        Sample::parse(&bytes).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    // Reads 8 or 16-bit PCM or 32-bit float WAV data. Channels are mixed
+    // down to mono
+    pub fn parse(bytes: &[u8]) -> Result<Self, String> {
+        if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+            return Err("not a WAV file".to_string());
+        }
+
+        // (format, channels, rate, bits per sample)
+        let mut format = None;
+        let mut pos = 12;
+        while pos + 8 <= bytes.len() {
+            let id = &bytes[pos..pos + 4];
+            let len = u32::from_le_bytes([
+                bytes[pos + 4],
+                bytes[pos + 5],
+                bytes[pos + 6],
+                bytes[pos + 7],
+            ]) as usize;
+            let body = &bytes[pos + 8..(pos + 8 + len).min(bytes.len())];
+            match id {
+                b"fmt " if body.len() >= 16 => {
+                    format = Some((
+                        u16::from_le_bytes([body[0], body[1]]),
+                        u16::from_le_bytes([body[2], body[3]]) as usize,
+                        u32::from_le_bytes([body[4], body[5], body[6], body[7]]),
+                        u16::from_le_bytes([body[14], body[15]]),
+                    ));
+                }
+                b"data" => {
+                    let (tag, channels, rate, bits) =
+                        format.ok_or_else(|| "data before the format chunk".to_string())?;
+                    let samples = decode(body, tag, bits)?;
+                    if channels == 0 || rate == 0 {
+                        return Err("invalid format".to_string());
+                    }
+                    let data = samples
+                        .chunks_exact(channels)
+                        .map(|frame| frame.iter().sum::<f32>() / channels as f32)
+                        .collect();
+                    return Ok(Sample { rate, data });
+                }
+                _ => {}
+            }
+            // Chunks are padded to an even length
+            pos += 8 + len + (len & 1);
+        }
+        Err("no audio data".to_string())
+    }
+}
+
+// Converts raw samples to floats between -1 and 1
+fn decode(body: &[u8], tag: u16, bits: u16) -> Result<Vec<f32>, String> {
+    match (tag, bits) {
+        (1, 8) => Ok(body.iter().map(|&b| (b as f32 - 128.0) / 128.0).collect()),
+        (1, 16) => Ok(body
+            .chunks_exact(2)
+            .map(|b| i16::from_le_bytes([b[0], b[1]]) as f32 / 32768.0)
+            .collect()),
+        (3, 32) => Ok(body
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect()),
+        _ => Err(format!(
+            "unsupported format {} with {} bits per sample, expected 8 or 16-bit PCM or 32-bit float",
+            tag, bits
+        )),
+    }
+}
+
+// Settings of the beep
+#[derive(Clone, Debug, PartialEq)]
+pub struct Tone {
+    pub frequency: f32, // Hz
+    pub volume: f32,    // Between 0 and 1
+    pub waveform: Waveform,
+    pub attack_ms: u32,  // Time to fade in when the beep starts
+    pub release_ms: u32, // Time to fade out when it stops
+}
+
+impl Default for Tone {
+    fn default() -> Self {
+        Tone {
+            frequency: DEF_TONE_FREQ,
+            volume: DEF_VOLUME,
+            waveform: Waveform::Square,
+            attack_ms: DEF_ENVELOPE_MS,
+            release_ms: DEF_ENVELOPE_MS,
+        }
+    }
+}
+
+// Generates the beep. Starting and stopping ramps the level up and down
+// instead of cutting the wave off mid period, which would click
+pub struct Synth {
+    tone: Tone,
+    rate: f32,     // Output samples per second
+    phase: f32,    // Position in the current period, from 0 to 1
+    position: f64, // Position in the sample of a WAV waveform
+    level: f32,    // Envelope level, from 0 to 1
+    gate: bool,    // Whether the beep is on
+    muted: bool,
+    noise: u32, // Noise generator state
+    noise_level: f32,
+}
+
+impl Synth {
+    pub fn new(tone: Tone, rate: u32) -> Self {
+        Synth {
+            tone,
+            rate: rate as f32,
+            phase: 0.0,
+            position: 0.0,
+            level: 0.0,
+            gate: false,
+            muted: false,
+            noise: 0x2545_f491,
+            noise_level: 0.0,
+        }
+    }
+
+    pub fn set_gate(&mut self, on: bool) {
+        self.gate = on;
+    }
+
+    pub fn set_muted(&mut self, muted: bool) {
+        self.muted = muted;
+    }
+
+    // Whether any sound is coming out, including a release still fading
+    pub fn is_sounding(&self) -> bool {
+        self.level > 0.0
+    }
+
+    pub fn fill(&mut self, out: &mut [f32]) {
+        let target = if self.gate && !self.muted { 1.0 } else { 0.0 };
+        // Level change per output sample
+        let attack = self.ramp_step(self.tone.attack_ms);
+        let release = self.ramp_step(self.tone.release_ms);
+
+        for x in out.iter_mut() {
+            if self.level < target {
+                self.level = (self.level + attack).min(target);
+            } else if self.level > target {
+                self.level = (self.level - release).max(target);
+            }
+            *x = if self.level > 0.0 {
+                self.next() * self.level * self.tone.volume
+            } else {
+                // Silent, start the next beep from the top of the period
+                self.phase = 0.0;
+                self.position = 0.0;
+                0.0
+            };
+        }
+    }
+
+    fn ramp_step(&self, ms: u32) -> f32 {
+        let samples = ms as f32 * self.rate / 1000.0;
+        if samples < 1.0 {
+            1.0
+        } else {
+            1.0 / samples
+        }
+    }
+
+    // Next sample of the waveform at full volume
+    fn next(&mut self) -> f32 {
+        let phase = self.phase;
+        let next_phase = (phase + self.tone.frequency / self.rate) % 1.0;
+        self.phase = next_phase;
+
+        match &self.tone.waveform {
+            Waveform::Square => {
+                if phase < 0.5 {
+                    1.0
+                } else {
+                    -1.0
+                }
+            }
+            Waveform::Sine => (phase * std::f32::consts::TAU).sin(),
+            Waveform::Triangle => 1.0 - 4.0 * (phase - 0.5).abs(),
+            Waveform::Noise => {
+                // New level at the start of each half period
+                if phase == 0.0 || (phase < 0.5) != (next_phase < 0.5) {
+                    // xorshift32
+                    self.noise ^= self.noise << 13;
+                    self.noise ^= self.noise >> 17;
+                    self.noise ^= self.noise << 5;
+                    self.noise_level = self.noise as f32 / u32::MAX as f32 * 2.0 - 1.0;
+                }
+                self.noise_level
+            }
+            Waveform::Sample(sample) => {
+                if sample.data.is_empty() {
+                    return 0.0;
+                }
+                let value = sample.data[self.position as usize % sample.data.len()];
+                self.position = (self.position + sample.rate as f64 / self.rate as f64)
+                    % sample.data.len() as f64;
+                value
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn ramps_without_clicks() {
+        let tone = Tone {
+            volume: 1.0,
+            attack_ms: 1,
+            release_ms: 2,
+            ..Tone::default()
+        };
+        // 1 ms is 10 samples
+        let mut synth = Synth::new(tone, 10_000);
+        let mut out = [0.0; 40];
+
+        synth.fill(&mut out);
+        assert!(out.iter().all(|&x| x == 0.0));
+
+        synth.set_gate(true);
+        synth.fill(&mut out);
+        assert!((out[0] - 0.1).abs() < 1e-6);
+        assert!(out[..10].windows(2).all(|w| (w[1] - w[0]).abs() < 0.11));
+        assert!((out[20].abs() - 1.0).abs() < 1e-6);
+
+        // Takes 20 samples to fade out
+        synth.set_gate(false);
+        synth.fill(&mut out);
+        assert!(out[..19].iter().all(|&x| x != 0.0));
+        assert!(out[20..].iter().all(|&x| x == 0.0));
+        assert!(!synth.is_sounding());
+
+        // Muting fades out as well
+        synth.set_gate(true);
+        synth.fill(&mut out);
+        synth.set_muted(true);
+        synth.fill(&mut out);
+        assert!(out[0] != 0.0);
+        assert!(out[20..].iter().all(|&x| x == 0.0));
+    }
+
+    #[test]
+    fn waveforms() {
+        assert_eq!(Waveform::parse("sine"), Ok(Waveform::Sine));
+        assert!(Waveform::parse("saw").is_err());
+        assert!(Waveform::parse("missing.wav").is_err());
+
+        for waveform in [Waveform::Sine, Waveform::Triangle, Waveform::Noise] {
+            let tone = Tone {
+                volume: 1.0,
+                waveform,
+                attack_ms: 0,
+                ..Tone::default()
+            };
+            let mut synth = Synth::new(tone, 44100);
+            synth.set_gate(true);
+            let mut out = [0.0; 1000];
+            synth.fill(&mut out);
+            assert!(out.iter().all(|x| (-1.0..=1.0).contains(x)));
+            assert!(out.iter().any(|&x| x > 0.5) && out.iter().any(|&x| x < -0.5));
+        }
+    }
+
+    #[test]
+    fn reads_wav() {
+        // 16-bit stereo, two frames
+        let mut wav = b"RIFF\0\0\0\0WAVEfmt \x10\0\0\0".to_vec();
+        wav.extend_from_slice(&[1, 0, 2, 0]); // PCM, 2 channels
+        wav.extend_from_slice(&8000u32.to_le_bytes());
+        wav.extend_from_slice(&32000u32.to_le_bytes());
+        wav.extend_from_slice(&[4, 0, 16, 0]);
+        wav.extend_from_slice(b"data\x08\0\0\0");
+        for s in [16384i16, 0, -32768, -32768] {
+            wav.extend_from_slice(&s.to_le_bytes());
+        }
+
+        assert_eq!(
+            Sample::parse(&wav),
+            Ok(Sample {
+                rate: 8000,
+                data: vec![0.25, -1.0]
+            })
+        );
+        assert!(Sample::parse(b"RIFF\0\0\0\0WAVE").is_err());
+        assert!(Sample::parse(b"not a wav").is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use std::{
+    error::Error,
+    io::{stdout, Write},
+    time::Duration,
+};
+
+use crossterm::{
+    cursor::{Hide, MoveTo, Show},
+    event::{self, Event, KeyCode, KeyEvent, KeyModifiers},
+    queue,
+    style::{Color, Print, ResetColor, SetBackgroundColor, SetForegroundColor},
+    terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen},
+    Result,
+};
+
+use crate::{
+    emulator::Chip8,
+    keyboard::{unmap_char, Keypad},
+    time, DISPLAY_HEIGHT, DISPLAY_WIDTH, FRAME_TIME_NS,
+};
+
+// Terminal frontend. Each character cell shows two pixels stacked
+// vertically using half-block glyphs, so the display takes 64x16 cells.
+//
+// Terminals only report key presses (and auto-repeats), never releases,
+// so a key counts as held for `key_hold_ns` after its last press event.
+pub struct Tui {
+    fgcol: Color,
+    bgcol: Color,
+    key_hold_ns: u128,
+    held_until: [u128; 16], // Time each key is held until
+}
+
+impl Tui {
+    // Switches the terminal to raw mode on the alternate screen
+    pub fn new(fg_col: (u8, u8, u8), bg_col: (u8, u8, u8), key_hold_ms: u64) -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        let mut out = stdout();
+        queue!(out, EnterAlternateScreen, Hide, Clear(ClearType::All))?;
+        out.flush()?;
+
+        Ok(Tui {
+            fgcol: rgb(fg_col),
+            bgcol: rgb(bg_col),
+            key_hold_ns: key_hold_ms as u128 * 1_000_000,
+            held_until: [0; 16],
+        })
+    }
+
+    // Restores the terminal to its normal state
+    pub fn restore(&mut self) -> Result<()> {
+        let mut out = stdout();
+        queue!(out, ResetColor, Show, LeaveAlternateScreen)?;
+        out.flush()?;
+        terminal::disable_raw_mode()
+    }
+
+    // Draws the framebuffer
+    pub fn render(&mut self, buffer: &[u8]) -> Result<()> {
+        let mut frame: Vec<u8> = Vec::new();
+        queue!(
+            frame,
+            SetForegroundColor(self.fgcol),
+            SetBackgroundColor(self.bgcol)
+        )?;
+        for row in 0..DISPLAY_HEIGHT / 2 {
+            queue!(frame, MoveTo(0, row as u16))?;
+            let top = &buffer[row * 2 * DISPLAY_WIDTH..][..DISPLAY_WIDTH];
+            let bottom = &buffer[(row * 2 + 1) * DISPLAY_WIDTH..][..DISPLAY_WIDTH];
+            let line: String = top
+                .iter()
+                .zip(bottom.iter())
+                .map(|(&t, &b)| match (t > 0, b > 0) {
+                    (false, false) => ' ',
+                    (true, false) => '▀',
+                    (false, true) => '▄',
+                    (true, true) => '█',
+                })
+                .collect();
+            queue!(frame, Print(line))?;
+        }
+        queue!(frame, ResetColor)?;
+
+        let mut out = stdout();
+        out.write_all(&frame)?;
+        out.flush()?;
+        Ok(())
+    }
+
+    // Handles pending terminal events without blocking. Returns false if
+    // the user asked to quit
+    pub fn poll_input(&mut self) -> Result<bool> {
+        while event::poll(Duration::from_secs(0))? {
+            if let Event::Key(key) = event::read()? {
+                if is_quit(&key) {
+                    return Ok(false);
+                }
+                self.press(&key);
+            }
+        }
+        Ok(true)
+    }
+
+    // Marks the key as held if it maps to the keypad
+    fn press(&mut self, key: &KeyEvent) {
+        if let KeyCode::Char(c) = key.code {
+            if let Some(value) = unmap_char(c) {
+                self.held_until[value as usize] = time::time_nanos() + self.key_hold_ns;
+            }
+        }
+    }
+}
+
+impl Keypad for Tui {
+    fn is_pressed(&mut self, key: u8) -> bool {
+        match self.held_until.get(key as usize) {
+            Some(&until) => time::time_nanos() < until,
+            None => false,
+        }
+    }
+}
+
+// Runs the machine in the terminal until Escape or Ctrl+C is pressed
+pub fn run(
+    chip8: &mut Chip8,
+    fg_col: (u8, u8, u8),
+    bg_col: (u8, u8, u8),
+    key_hold_ms: u64,
+) -> std::result::Result<(), Box<dyn Error>> {
+    let mut tui = Tui::new(fg_col, bg_col, key_hold_ms)?;
+    let result = main_loop(chip8, &mut tui);
+    tui.restore()?;
+    result
+}
+
+fn main_loop(chip8: &mut Chip8, tui: &mut Tui) -> std::result::Result<(), Box<dyn Error>> {
+    let mut last_frame_t: u128 = time::time_nanos();
+    let mut frame_dirty = true;
+    let mut beeping = false;
+
+    loop {
+        let t: u128 = time::time_nanos();
+
+        chip8.cycle(t, tui)?;
+
+        if chip8.display_clear_flag || chip8.display_update_flag {
+            frame_dirty = true;
+        }
+
+        // Input and drawing happen once per frame
+        if t - last_frame_t > FRAME_TIME_NS {
+            if !tui.poll_input()? {
+                return Ok(());
+            }
+            if frame_dirty {
+                tui.render(&chip8.display)?;
+                frame_dirty = false;
+            }
+            last_frame_t = t;
+        }
+
+        // Ring the terminal bell when the beep starts
+        if chip8.beep_flag && !beeping {
+            let mut out = stdout();
+            out.write_all(b"\x07")?;
+            out.flush()?;
+        }
+        beeping = chip8.beep_flag;
+    }
+}
+
+fn is_quit(key: &KeyEvent) -> bool {
+    key.code == KeyCode::Esc
+        || (key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL))
+}
+
+fn rgb(col: (u8, u8, u8)) -> Color {
+    Color::Rgb {
+        r: col.0,
+        g: col.1,
+        b: col.2,
+    }
+}
@@ -15,7 +15,20 @@ pub fn hex_to_col(hexcol: &str) -> Result<(u8, u8, u8), String> {
 
 pub fn hex_to_u8(hexbyte: &str) -> Result<u8, String> {
     match hex::decode(hexbyte) {
-        Ok(val) => Ok(*val.get(0).unwrap()),
+        Ok(val) => Ok(*val.first().unwrap()),
         Err(e) => Err(format!("{:?}", e)),
     }
 }
+
+// CRC-32 (ISO 3309), as used by PNG chunks and to identify ROMs
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+    for &byte in data {
+        crc ^= byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -0,0 +1,126 @@
+// Placement of the framebuffer in a window of any size. The frame keeps
+// its aspect ratio and is centered, with bars filling the rest.
+
+// How the frame is scaled up to the window
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scaling {
+    // Largest whole multiple of the frame size that fits, so every pixel
+    // has the same size
+    Integer,
+    // As large as fits, by any factor
+    Fit,
+}
+
+impl Scaling {
+    pub fn parse(mode: &str) -> Result<Self, String> {
+        match mode {
+            "integer" => Ok(Scaling::Integer),
+            "fit" => Ok(Scaling::Fit),
+            _ => Err(format!(
+                "Unknown scaling '{}', expected integer or fit",
+                mode
+            )),
+        }
+    }
+}
+
+// Shape of the frame on screen
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Aspect {
+    // Square pixels
+    Native,
+    // Stretched to 4:3, like on the TV sets and monitors of the original
+    // machines
+    Classic,
+}
+
+impl Aspect {
+    pub fn parse(aspect: &str) -> Result<Self, String> {
+        match aspect {
+            "native" => Ok(Aspect::Native),
+            "4:3" => Ok(Aspect::Classic),
+            _ => Err(format!(
+                "Unknown aspect '{}', expected native or 4:3",
+                aspect
+            )),
+        }
+    }
+
+    // Width of the frame on screen per unit of height
+    fn ratio(&self, width: u32, height: u32) -> f64 {
+        match self {
+            Aspect::Native => width as f64 / height as f64,
+            Aspect::Classic => 4.0 / 3.0,
+        }
+    }
+}
+
+// Rectangle the frame is drawn to, as (x, y, width, height)
+pub type Rect = (i32, i32, u32, u32);
+
+// Places a frame of the given resolution in a window of the given size
+pub fn place(window: (u32, u32), frame: (u32, u32), scaling: Scaling, aspect: Aspect) -> Rect {
+    let (win_w, win_h) = window;
+    let ratio = aspect.ratio(frame.0, frame.1);
+
+    // Height of the frame on screen, the width follows from the aspect
+    let fit_h = (win_h as f64).min(win_w as f64 / ratio);
+    let height = match scaling {
+        Scaling::Integer => {
+            let factor = (fit_h / frame.1 as f64).floor();
+            // Windows smaller than the frame still show all of it
+            if factor >= 1.0 {
+                factor * frame.1 as f64
+            } else {
+                fit_h
+            }
+        }
+        Scaling::Fit => fit_h,
+    };
+    let w = ((height * ratio).round() as u32).min(win_w);
+    let h = (height.round() as u32).min(win_h);
+    (((win_w - w) / 2) as i32, ((win_h - h) / 2) as i32, w, h)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn letterboxes() {
+        let frame = (64, 32);
+        // Exactly the window
+        assert_eq!(
+            place((640, 320), frame, Scaling::Integer, Aspect::Native),
+            (0, 0, 640, 320)
+        );
+        // Bars above and below, whole multiples only
+        assert_eq!(
+            place((700, 500), frame, Scaling::Integer, Aspect::Native),
+            (30, 90, 640, 320)
+        );
+        assert_eq!(
+            place((700, 500), frame, Scaling::Fit, Aspect::Native),
+            (0, 75, 700, 350)
+        );
+        // Bars on the sides
+        assert_eq!(
+            place((1000, 300), frame, Scaling::Fit, Aspect::Native),
+            (200, 0, 600, 300)
+        );
+        // 4:3 fills a 4:3 window
+        assert_eq!(
+            place((800, 600), frame, Scaling::Fit, Aspect::Classic),
+            (0, 0, 800, 600)
+        );
+        assert_eq!(
+            place((1920, 1080), frame, Scaling::Integer, Aspect::Classic),
+            (256, 12, 1408, 1056)
+        );
+        // Smaller than the frame
+        assert_eq!(
+            place((32, 32), frame, Scaling::Integer, Aspect::Native),
+            (0, 8, 32, 16)
+        );
+    }
+}
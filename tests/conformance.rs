@@ -0,0 +1,115 @@
+// Golden-frame conformance tests. Each test ROM is run headlessly until its
+// display settles, then the framebuffer is compared against a checked-in
+// ASCII dump in tests/golden.
+//
+// Set CHIP8_BLESS=1 to (re)generate the golden files from the current
+// emulator output, and check the new goldens by hand before committing
+// them. A ROM missing from roms/ fails its test.
+
+use std::{env, fs, path::PathBuf};
+
+use chip_8::{
+    emulator::Chip8,
+    headless::{self, DumpFormat, ScriptedKeypad},
+    DEF_IPS, DISPLAY_HEIGHT, DISPLAY_WIDTH, FONTS,
+};
+
+// Frames the display must stay unchanged for to count as settled
+const SETTLE_FRAMES: u64 = 60;
+// Frames after which a ROM that keeps drawing fails the test
+const MAX_FRAMES: u64 = 3600;
+
+struct Case {
+    name: &'static str,
+    rom: &'static str,
+    // Key script in the `run --keys` format
+    keys: &'static str,
+    // Frames to run before checking whether the display settled, so all
+    // scripted key presses happen
+    min_frames: u64,
+}
+
+fn root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+}
+
+// Runs the ROM until the display settles and returns its ASCII dump
+fn run_case(case: &Case) -> String {
+    let path = root().join("roms").join(case.rom);
+    let rom = fs::read(&path)
+        .unwrap_or_else(|e| panic!("{}: could not read {}: {}", case.name, path.display(), e));
+
+    let instruction_time_ns = 1e9 as u128 / DEF_IPS as u128;
+    let mut chip8 = Chip8::new(rom, FONTS, 0, instruction_time_ns, false);
+    chip8.seed(0);
+    let mut keypad = ScriptedKeypad::parse(case.keys).unwrap();
+
+    headless::run(&mut chip8, case.min_frames, &mut keypad)
+        .unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+
+    let mut last = headless::framebuffer_hash(&chip8.display);
+    let mut stable = 0;
+    let mut frame = case.min_frames;
+    while stable < SETTLE_FRAMES {
+        assert!(
+            frame < MAX_FRAMES,
+            "{}: display did not settle after {} frames",
+            case.name,
+            MAX_FRAMES
+        );
+        keypad.set_frame(frame);
+        chip8
+            .run_frame(&mut keypad)
+            .unwrap_or_else(|e| panic!("{}: {}", case.name, e));
+        let hash = headless::framebuffer_hash(&chip8.display);
+        stable = if hash == last { stable + 1 } else { 0 };
+        last = hash;
+        frame += 1;
+    }
+
+    headless::dump(
+        &chip8.display,
+        DISPLAY_WIDTH,
+        DISPLAY_HEIGHT,
+        DumpFormat::Ascii,
+    )
+}
+
+fn check(case: Case) {
+    let actual = run_case(&case);
+
+    let golden = root()
+        .join("tests/golden")
+        .join(format!("{}.txt", case.name));
+    if env::var_os("CHIP8_BLESS").is_some() {
+        fs::write(&golden, &actual).unwrap();
+        return;
+    }
+
+    let expected = fs::read_to_string(&golden).unwrap_or_else(|_| {
+        panic!(
+            "{}: missing golden {}, run with CHIP8_BLESS=1 to create it",
+            case.name,
+            golden.display()
+        )
+    });
+    assert!(
+        actual == expected,
+        "{}: framebuffer differs from {}\n\nexpected:\n{}\nactual:\n{}\n\
+        run with CHIP8_BLESS=1 to update the golden if the change is intended",
+        case.name,
+        golden.display(),
+        expected,
+        actual
+    );
+}
+
+#[test]
+fn opcodes() {
+    check(Case {
+        name: "test_opcode",
+        rom: "test_opcode.ch8",
+        keys: "",
+        min_frames: 0,
+    });
+}
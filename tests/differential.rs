@@ -0,0 +1,161 @@
+// Differential tests of the threaded backend: every ROM in roms/ and a set
+// of random programs run on both backends, which must end in exactly the
+// same state, frame by frame.
+
+use std::{fs, path::PathBuf};
+
+use chip_8::{
+    emulator::{Backend, Chip8, Chip8Result, Status},
+    headless::ScriptedKeypad,
+    FONTS,
+};
+
+// Everything the program can observe or change
+#[derive(Debug, PartialEq)]
+struct State {
+    registers: Vec<u8>,
+    index: u16,
+    ram: Vec<u8>,
+    stack: Vec<u16>,
+    istack: usize,
+    pc: usize,
+    dt: u8,
+    st: u8,
+    display: Vec<u8>,
+    beep: bool,
+    instructions: u64,
+    status: Status,
+}
+
+fn state(chip8: &Chip8) -> State {
+    State {
+        registers: chip8.registers.to_vec(),
+        index: chip8.index,
+        ram: chip8.ram.to_vec(),
+        stack: chip8.stack.to_vec(),
+        istack: chip8.istack,
+        pc: chip8.pc,
+        dt: chip8.dt,
+        st: chip8.st,
+        display: chip8.display.to_vec(),
+        beep: chip8.beep_flag,
+        instructions: chip8.instructions(),
+        status: chip8.status,
+    }
+}
+
+fn machine(rom: &[u8], instruction_time_ns: u128, backend: Backend) -> Chip8 {
+    let mut chip8 = Chip8::new(rom.to_vec(), FONTS, 0, instruction_time_ns, false);
+    chip8.seed(7);
+    chip8.backend = backend;
+    chip8
+}
+
+// Runs a ROM on both backends, comparing their states after every frame
+fn compare(name: &str, rom: &[u8], instruction_time_ns: u128, frames: u64, keys: &str) {
+    let mut interpreter = machine(rom, instruction_time_ns, Backend::Interpreter);
+    let mut threaded = machine(rom, instruction_time_ns, Backend::Threaded);
+    let mut keypad = ScriptedKeypad::parse(keys).unwrap();
+
+    for frame in 0..frames {
+        keypad.set_frame(frame);
+        let expected: Chip8Result<()> = interpreter.run_frame(&mut keypad);
+        let result = threaded.run_frame(&mut keypad);
+        assert_eq!(
+            result, expected,
+            "{}: result differs at frame {}",
+            name, frame
+        );
+        assert!(
+            state(&threaded) == state(&interpreter),
+            "{}: state differs at frame {}",
+            name,
+            frame
+        );
+        if expected.is_err() {
+            break;
+        }
+    }
+}
+
+#[test]
+fn roms_match_interpreter() {
+    let dir = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("roms");
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let rom = fs::read(&path).unwrap();
+        let name = path.file_name().unwrap().to_string_lossy();
+        compare(
+            &name,
+            &rom,
+            1_000_000,
+            1200,
+            "30:5:20,90:4:30,200:6:60,400:8:30",
+        );
+        compare(&name, &rom, 10_000, 120, "10:5:20,60:6:20");
+    }
+}
+
+#[test]
+fn self_modifying_code_matches_interpreter() {
+    // A loop adding to V1 whose addend is V0, stored over it with FX55
+    let program: [u16; 5] = [
+        0x7101, // 200: V1 += addend at 0x201
+        0x7003, // 202: V0 += 3
+        0xA201, // 204: I = 0x201
+        0xF055, // 206: store V0 at 0x201
+        0x1200, // 208: loop
+    ];
+    let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+    compare("self-modifying", &rom, 1_000_000, 300, "");
+}
+
+#[test]
+fn spinning_loops_match_interpreter() {
+    // A delay timer wait whose first lap sees V1 from before the loop, and
+    // a key poll
+    let program: [u16; 12] = [
+        0x6005, // 200: V0 = 5
+        0xF015, // 202: DT = V0
+        0x3005, // 204: skip to the loop, so it compiles to its own block
+        0x0000, // 206: not run
+        0x3105, // 208: skip if V1 == 5, only after the first lap
+        0xF107, // 20A: V1 = DT
+        0x3100, // 20C: leave once the timer ran out
+        0x1208, // 20E: loop
+        0x7201, // 210: V2 += 1
+        0xE09E, // 212: skip if key V0 is down
+        0x1212, // 214: poll again
+        0x1200, // 216: restart
+    ];
+    let rom: Vec<u8> = program.iter().flat_map(|i| i.to_be_bytes()).collect();
+    compare("spinning", &rom, 1_000_000, 300, "20:5:10,100:5:3");
+    compare("spinning", &rom, 10_000, 300, "20:5:10,100:5:3");
+}
+
+#[test]
+fn random_programs_match_interpreter() {
+    // xorshift, so the programs are the same on every run
+    let mut rng: u64 = 0x2545_F491_4F6C_DD1D;
+    let mut next = move || {
+        rng ^= rng << 13;
+        rng ^= rng >> 7;
+        rng ^= rng << 17;
+        rng
+    };
+
+    for program in 0..300 {
+        let mut rom = Vec::new();
+        for _ in 0..128 {
+            let instr = next() as u16;
+            // Keep most jumps and calls inside the program
+            let instr = match instr & 0xF000 {
+                0x1000 | 0x2000 | 0xA000 | 0xB000 => (instr & 0xF0FF) | 0x0200,
+                _ => instr,
+            };
+            rom.extend_from_slice(&instr.to_be_bytes());
+        }
+        let name = format!("random program {}", program);
+        compare(&name, &rom, 1_000_000, 60, "5:1:10,20:a:5,40:f:10");
+    }
+}